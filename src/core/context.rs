@@ -0,0 +1,169 @@
+use crate::core::lib::{ContextProvider, DiracError, DiracResult, SessionState};
+
+/// Marker appended to sections cut down to their budget.
+const TRUNCATED: &str = "\n[truncated]";
+
+#[derive(Debug)]
+struct ProviderEntry {
+    provider: Box<dyn ContextProvider>,
+    enabled: bool,
+    budget: usize,
+}
+
+/// Assembles the environment details sent along with every AI request from
+/// the registered providers, each one limited to its own size budget.
+#[derive(Debug, Default)]
+pub struct ContextManager {
+    providers: Vec<ProviderEntry>,
+}
+
+/// State of one registered provider, as listed by `ContextManager::list`.
+#[derive(Debug, Clone, Copy)]
+pub struct ProviderInfo<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    pub enabled: bool,
+    pub budget: usize,
+}
+
+impl ContextManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an enabled provider with its default budget.
+    pub fn register(&mut self, provider: Box<dyn ContextProvider>) {
+        let budget = provider.default_budget();
+        self.providers.push(ProviderEntry {
+            provider,
+            enabled: true,
+            budget,
+        });
+    }
+
+    fn entry_mut(&mut self, name: &str) -> DiracResult<&mut ProviderEntry> {
+        self.providers
+            .iter_mut()
+            .find(|entry| entry.provider.name() == name)
+            .ok_or_else(|| DiracError::InputError(format!("Unknown context provider '{}'", name)))
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> DiracResult<()> {
+        self.entry_mut(name)?.enabled = enabled;
+        Ok(())
+    }
+
+    /// Limits the section of provider `name` to `budget` characters.
+    pub fn set_budget(&mut self, name: &str, budget: usize) -> DiracResult<()> {
+        self.entry_mut(name)?.budget = budget;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<ProviderInfo<'_>> {
+        self.providers
+            .iter()
+            .map(|entry| ProviderInfo {
+                name: entry.provider.name(),
+                description: entry.provider.description(),
+                enabled: entry.enabled,
+                budget: entry.budget,
+            })
+            .collect()
+    }
+
+    /// Collects every enabled provider into titled sections, in registration
+    /// order. Providers with nothing to say, or a budget too small to say
+    /// anything, are left out.
+    pub async fn assemble(&self, session: &SessionState<'_>) -> String {
+        let mut sections = Vec::new();
+        for entry in self.providers.iter().filter(|entry| entry.enabled && entry.budget > 0) {
+            let Some(text) = entry.provider.collect(session).await else {
+                continue;
+            };
+            let text = text.trim();
+            if let Some(text) = truncate(text, entry.budget).filter(|text| !text.is_empty()) {
+                sections.push(format!("{}:\n{}", entry.provider.name(), text));
+            }
+        }
+        sections.join("\n\n")
+    }
+}
+
+/// Cuts `text` down to at most `budget` characters, marker included, at a
+/// line break when one is close enough. `None` when nothing fits next to the
+/// marker, as a cut section without it would pass for the whole.
+fn truncate(text: &str, budget: usize) -> Option<String> {
+    if text.chars().count() <= budget {
+        return Some(text.to_string());
+    }
+    let keep = budget.checked_sub(TRUNCATED.chars().count()).filter(|keep| *keep > 0)?;
+    let cut = text.char_indices().nth(keep).map(|(i, _)| i).unwrap_or(text.len());
+    let head = &text[..cut];
+    let head = match head.rfind('\n') {
+        Some(line_end) if line_end >= head.len() / 2 => &head[..line_end],
+        _ => head,
+    };
+    Some(format!("{}{}", head, TRUNCATED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[derive(Debug)]
+    struct Fixed(&'static str, &'static str);
+
+    #[async_trait::async_trait]
+    impl ContextProvider for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn description(&self) -> &str {
+            ""
+        }
+
+        fn default_budget(&self) -> usize {
+            100
+        }
+
+        async fn collect(&self, _session: &SessionState<'_>) -> Option<String> {
+            Some(self.1.to_string())
+        }
+    }
+
+    #[test]
+    fn truncate_keeps_within_the_budget() {
+        assert_eq!(truncate("short", 5).as_deref(), Some("short"));
+        let text = "first line\nsecond line\nthird line, a longer one";
+        assert_eq!(truncate(text, 36).as_deref(), Some("first line\nsecond line\n[truncated]"));
+        assert_eq!(truncate(text, 30).as_deref(), Some("first line\n[truncated]"));
+        for budget in 0..=text.len() {
+            if let Some(cut) = truncate(text, budget) {
+                assert!(cut.chars().count() <= budget, "{} > {}", cut, budget);
+            }
+        }
+    }
+
+    #[test]
+    fn truncate_drops_what_the_marker_does_not_fit() {
+        let marker = TRUNCATED.chars().count();
+        assert_eq!(truncate("a long enough section", marker - 1), None);
+        assert_eq!(truncate("a long enough section", marker), None);
+        assert_eq!(truncate("a long enough section", marker + 1).as_deref(), Some("a\n[truncated]"));
+    }
+
+    #[tokio::test]
+    async fn assemble_leaves_out_disabled_and_unfitting_sections() {
+        let mut context = ContextManager::new();
+        context.register(Box::new(Fixed("shell", "bash")));
+        context.register(Box::new(Fixed("git", "branch: main")));
+        context.register(Box::new(Fixed("distro", "Debian GNU/Linux 12 (bookworm)")));
+        context.set_enabled("git", false).unwrap();
+        context.set_budget("distro", 5).unwrap();
+
+        let session = SessionState { cwd: Path::new("/"), recent_commands: &[], last_output: None, env: &[] };
+        assert_eq!(context.assemble(&session).await, "shell:\nbash");
+    }
+}
@@ -0,0 +1,268 @@
+use crate::core::lib::{ChatMessage, ChatRole};
+use crate::core::suggestion::Suggestion;
+use std::collections::VecDeque;
+
+/// Rough number of tokens the conversation may add to a prompt.
+pub const DEFAULT_TOKEN_BUDGET: usize = 2048;
+
+/// Turns kept in memory, regardless of how many fit the budget.
+const MAX_TURNS: usize = 50;
+
+/// Command output longer than this is cut before it is remembered.
+const MAX_OUTPUT_CHARS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub enum TurnOutcome {
+    Pending,
+    Declined,
+    Executed { command: String, success: bool, output: String },
+    /// Stopped with CTRL-C or CTRL-\ before it finished.
+    Interrupted { command: String, output: String },
+}
+
+/// One natural-language request and what came of it.
+#[derive(Debug, Clone)]
+pub struct Turn {
+    pub request: String,
+    pub suggestion: Suggestion,
+    pub outcome: TurnOutcome,
+}
+
+impl Turn {
+    fn messages(&self) -> Vec<ChatMessage> {
+        let mut messages = vec![
+            ChatMessage::new(ChatRole::User, self.request.as_str()),
+            ChatMessage::new(
+                ChatRole::Assistant,
+                serde_json::to_string(&self.suggestion).unwrap_or_default(),
+            ),
+        ];
+        match &self.outcome {
+            TurnOutcome::Pending => {}
+            TurnOutcome::Declined => {
+                messages.push(ChatMessage::new(ChatRole::User, "I did not run that command."));
+            }
+            TurnOutcome::Executed { command, success, output } => {
+                let status = if *success { "succeeded" } else { "failed" };
+                let content = if output.is_empty() {
+                    format!("I ran `{}` and it {} with no output.", command, status)
+                } else {
+                    format!("I ran `{}` and it {}. Output:\n{}", command, status, output)
+                };
+                messages.push(ChatMessage::new(ChatRole::User, content));
+            }
+            TurnOutcome::Interrupted { command, output } => {
+                let content = if output.is_empty() {
+                    format!("I ran `{}` and interrupted it before it finished, with no output.", command)
+                } else {
+                    format!("I ran `{}` and interrupted it before it finished. Output:\n{}", command, output)
+                };
+                messages.push(ChatMessage::new(ChatRole::User, content));
+            }
+        }
+        messages
+    }
+}
+
+/// Per-session memory of AI requests, replayed to the model as a sliding
+/// window of the most recent turns that fit the token budget.
+#[derive(Debug)]
+pub struct Conversation {
+    turns: VecDeque<Turn>,
+    token_budget: usize,
+}
+
+impl Conversation {
+    pub fn new(token_budget: usize) -> Self {
+        Self {
+            turns: VecDeque::new(),
+            token_budget,
+        }
+    }
+
+    pub fn push_turn(&mut self, request: &str, suggestion: &Suggestion) {
+        if self.turns.len() == MAX_TURNS {
+            self.turns.pop_front();
+        }
+        self.turns.push_back(Turn {
+            request: request.to_string(),
+            suggestion: suggestion.clone(),
+            outcome: TurnOutcome::Pending,
+        });
+    }
+
+    /// Records what happened to the latest suggestion.
+    pub fn set_outcome(&mut self, outcome: TurnOutcome) {
+        let outcome = match outcome {
+            TurnOutcome::Executed { command, success, output } => TurnOutcome::Executed {
+                command,
+                success,
+                output: truncate(output.trim(), MAX_OUTPUT_CHARS),
+            },
+            TurnOutcome::Interrupted { command, output } => TurnOutcome::Interrupted {
+                command,
+                output: truncate(output.trim(), MAX_OUTPUT_CHARS),
+            },
+            other => other,
+        };
+        if let Some(turn) = self.turns.back_mut() {
+            turn.outcome = outcome;
+        }
+    }
+
+    /// Messages for the newest turns that fit the token budget, oldest first.
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        let mut window = Vec::new();
+        let mut used = 0;
+        for turn in self.turns.iter().rev() {
+            let messages = turn.messages();
+            let tokens: usize = messages.iter().map(|m| estimate_tokens(&m.content)).sum();
+            if used + tokens > self.token_budget {
+                break;
+            }
+            used += tokens;
+            window.push(messages);
+        }
+        window.into_iter().rev().flatten().collect()
+    }
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_BUDGET)
+    }
+}
+
+/// Approximates the token count of `text` at four characters per token.
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let truncated: String = text.chars().take(max_chars).collect();
+    format!("{}\n... (truncated)", truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::suggestion::Risk;
+
+    fn suggestion(command: &str) -> Suggestion {
+        Suggestion {
+            command: command.to_string(),
+            explanation: String::new(),
+            risk: Risk::Low,
+            alternatives: Vec::new(),
+            requires_sudo: false,
+            probes: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    fn executed(command: &str, output: &str) -> TurnOutcome {
+        TurnOutcome::Executed { command: command.to_string(), success: true, output: output.to_string() }
+    }
+
+    fn tokens(messages: &[ChatMessage]) -> usize {
+        messages.iter().map(|m| estimate_tokens(&m.content)).sum()
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn only_the_newest_turns_within_the_budget_are_sent_oldest_first() {
+        let turns: Vec<Turn> = ["ls", "pwd", "whoami"]
+            .iter()
+            .map(|command| Turn {
+                request: format!("run {}", command),
+                suggestion: suggestion(command),
+                outcome: executed(command, "ok"),
+            })
+            .collect();
+        let budget = tokens(&turns[1].messages()) + tokens(&turns[2].messages());
+        let mut conversation = Conversation::new(budget);
+        for turn in &turns {
+            conversation.push_turn(&turn.request, &turn.suggestion);
+            conversation.set_outcome(turn.outcome.clone());
+        }
+
+        let messages = conversation.messages();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0].content, "run pwd");
+        assert_eq!(messages[3].content, "run whoami");
+        assert!(matches!(messages[0].role, ChatRole::User));
+        assert!(matches!(messages[1].role, ChatRole::Assistant));
+
+        // One token short of the older turn, only the newest one fits
+        let mut conversation = Conversation::new(budget - 1);
+        for turn in &turns {
+            conversation.push_turn(&turn.request, &turn.suggestion);
+            conversation.set_outcome(turn.outcome.clone());
+        }
+        assert_eq!(conversation.messages()[0].content, "run whoami");
+    }
+
+    #[test]
+    fn a_newest_turn_over_the_budget_sends_nothing() {
+        let mut conversation = Conversation::new(1);
+        conversation.push_turn("list files", &suggestion("ls"));
+        assert!(conversation.messages().is_empty());
+    }
+
+    #[test]
+    fn oldest_turns_are_forgotten_past_max_turns() {
+        let mut conversation = Conversation::new(usize::MAX);
+        for n in 0..=MAX_TURNS {
+            conversation.push_turn(&format!("request {}", n), &suggestion("true"));
+        }
+        let messages = conversation.messages();
+        assert_eq!(messages.len(), 2 * MAX_TURNS);
+        assert_eq!(messages[0].content, "request 1");
+    }
+
+    #[test]
+    fn outcomes_are_told_to_the_model() {
+        let outcomes = [
+            (TurnOutcome::Declined, "I did not run that command."),
+            (executed("ls", ""), "I ran `ls` and it succeeded with no output."),
+            (
+                TurnOutcome::Executed { command: "make".to_string(), success: false, output: "  no rule  \n".to_string() },
+                "I ran `make` and it failed. Output:\nno rule",
+            ),
+            (
+                TurnOutcome::Interrupted { command: "top".to_string(), output: String::new() },
+                "I ran `top` and interrupted it before it finished, with no output.",
+            ),
+        ];
+        for (outcome, told) in outcomes {
+            let mut conversation = Conversation::default();
+            conversation.push_turn("request", &suggestion("true"));
+            conversation.set_outcome(outcome);
+            let messages = conversation.messages();
+            assert_eq!(contents(&messages)[2..], [told]);
+            assert!(matches!(messages[2].role, ChatRole::User));
+        }
+
+        // Still pending: just the request and the answer
+        let mut conversation = Conversation::default();
+        conversation.push_turn("request", &suggestion("true"));
+        assert_eq!(conversation.messages().len(), 2);
+    }
+
+    #[test]
+    fn long_output_is_truncated_before_it_is_remembered() {
+        let mut conversation = Conversation::new(usize::MAX);
+        conversation.push_turn("show the log", &suggestion("cat log"));
+        conversation.set_outcome(executed("cat log", &"x".repeat(5 * MAX_OUTPUT_CHARS)));
+
+        let told = &conversation.messages()[2].content;
+        assert!(told.ends_with(&format!("{}\n... (truncated)", "x".repeat(10))), "{}", told);
+        assert_eq!(told.matches('x').count(), MAX_OUTPUT_CHARS);
+    }
+}
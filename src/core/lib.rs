@@ -1,22 +1,198 @@
+use crate::core::suggestion::Suggestion;
+use futures_util::Stream;
+use serde::Serialize;
 use std::error::Error;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
+use std::path::Path;
+use std::pin::Pin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
+/// A single message of a chat-style conversation with the model.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// What the model is asked to do; each kind has its own prompt template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    /// Turn a natural language request into a command
+    Generate,
+    /// Explain why a command failed and propose a fix
+    Diagnose,
+    /// Describe what a command does
+    Explain,
+    /// Break a request down into an ordered list of commands
+    Plan,
+    /// Answer a question about the output of the last command
+    Ask,
+}
+
+impl PromptKind {
+    pub const ALL: [PromptKind; 5] = [
+        PromptKind::Generate,
+        PromptKind::Diagnose,
+        PromptKind::Explain,
+        PromptKind::Plan,
+        PromptKind::Ask,
+    ];
+
+    /// Name of the kind, also used as the template file stem.
+    pub fn name(self) -> &'static str {
+        match self {
+            PromptKind::Generate => "generate",
+            PromptKind::Diagnose => "diagnose",
+            PromptKind::Explain => "explain",
+            PromptKind::Plan => "plan",
+            PromptKind::Ask => "ask",
+        }
+    }
+}
+
+/// Chunks of model output, yielded as the backend produces them.
+pub type AIStream = Pin<Box<dyn Stream<Item = DiracResult<String>> + Send>>;
+
+/// A streamed answer together with the backend that is producing it.
+pub struct AIResponse {
+    pub provider: String,
+    /// Whether the answer was replayed from the response cache
+    pub cached: bool,
+    pub stream: AIStream,
+}
 
 #[async_trait::async_trait]
-pub trait AIProcessor {
-    async fn process<'a>(&'a self, input: &'a str, context: &'a str) -> DiracResult<String>;
+pub trait AIProcessor: Send + Sync {
+    /// Backend and model name, e.g. `ollama:qwen2.5:3b`.
+    fn name(&self) -> String;
+
+    /// The model answering requests, for backends that have a choice of models.
+    fn model(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the model is in memory, as loading it first can take much
+    /// longer than answering. `None` when the backend cannot tell.
+    async fn is_model_loaded(&self) -> Option<bool> {
+        None
+    }
+
+    /// Switches the model for the rest of the session.
+    fn set_model(&self, model: &str) -> DiracResult<()> {
+        Err(DiracError::InputError(format!("{} cannot switch to model '{}'", self.name(), model)))
+    }
+
+    /// Generates a command for `input` using the prompt of `kind`. `history`
+    /// holds the earlier turns of the session, oldest first, so follow-up
+    /// requests can refer to them.
+    async fn process<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<Suggestion>;
+
+    /// Streams the raw JSON answer as it is generated, to be parsed with
+    /// `Suggestion::parse` once complete. Backends without streaming support
+    /// yield the whole response from `process` as a single chunk.
+    async fn process_stream<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<AIResponse> {
+        let suggestion = self.process(kind, input, context, history).await?;
+        let response = serde_json::to_string(&suggestion)
+            .map_err(|e| DiracError::AIProcessingError(format!("Failed to encode suggestion: {}", e)))?;
+        Ok(AIResponse {
+            provider: self.name(),
+            cached: false,
+            stream: Box::pin(futures_util::stream::once(async move { Ok(response) })),
+        })
+    }
 }
 
+#[allow(async_fn_in_trait)]
 pub trait CommandExecutor {
     async fn execute(&self, command: &str) -> DiracResult<String>;
 }
 
 pub trait TerminalInterface {
     fn read_line(&mut self, prompt: &str) -> DiracResult<String>;
+    /// Reads a line with `initial` pre-filled for the user to edit.
+    fn read_line_with_initial(&mut self, prompt: &str, initial: &str) -> DiracResult<String> {
+        let _ = initial;
+        self.read_line(prompt)
+    }
     fn add_history(&mut self, line: &str);
     fn display_output(&self, output: &str);
+    /// Shows `output` without ending the line, e.g. an answer as it streams
+    /// in. The next `display_output` continues the same line.
+    fn display_partial(&self, output: &str) {
+        self.display_output(output)
+    }
     fn display_error(&self, error: &str);
 }
 
+/// A command run during the session and how it ended.
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    pub command: String,
+    /// `None` when the command timed out or could not be started
+    pub exit_code: Option<i32>,
+}
+
+/// What the last command printed, truncated and with secrets redacted.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub command: String,
+    /// `None` when the command timed out or could not be started
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// What context providers can see of the running session.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionState<'a> {
+    pub cwd: &'a Path,
+    /// Commands run so far, oldest first
+    pub recent_commands: &'a [CommandRecord],
+    pub last_output: Option<&'a CommandOutput>,
+    /// Exported variables of the shell commands run in, which `export`,
+    /// `source` and the like change as the session goes on
+    pub env: &'a [(OsString, OsString)],
+}
+
+impl SessionState<'_> {
+    /// The value of the session variable `name`, if it is set.
+    pub fn var(&self, name: &str) -> Option<&OsStr> {
+        self.env
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_os_str())
+    }
+}
+
+/// Contributes one section of environment details to AI prompts.
+#[async_trait::async_trait]
+pub trait ContextProvider: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    /// Largest section this provider contributes by default, in characters.
+    fn default_budget(&self) -> usize;
+    /// Describes the environment, or `None` when there is nothing relevant.
+    /// Runs on the async path of every request, so anything slow must be
+    /// awaited with a timeout rather than block.
+    async fn collect(&self, session: &SessionState<'_>) -> Option<String>;
+}
+
 pub trait Plugin: std::fmt::Debug {
     fn name(&self) -> &str;
     fn description(&self) -> &str;
@@ -25,15 +201,32 @@ pub trait Plugin: std::fmt::Debug {
 
 pub trait PluginManager {
     fn register_plugin(&mut self, plugin: Box<dyn Plugin>);
-    fn get_plugin(&self, name: &str) -> Option<&Box<dyn Plugin>>;
+    /// The plugin called `name`, borrowed as `&dyn Plugin` rather than
+    /// `&Box<dyn Plugin>` so managers need not store plugins boxed.
+    fn get_plugin(&self, name: &str) -> Option<&dyn Plugin>;
     fn list_plugins(&self) -> Vec<(&str, &str)>;
 }
 
+// The `...Error` variants predate clippy's check and are matched all over;
+// renaming them is not worth breaking every caller.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum DiracError {
     CommandExecutionError(String),
     AIProcessingError(String),
+    /// The backend could not be reached or cannot serve the model; another
+    /// backend may still be able to answer.
+    AIUnavailableError(String),
+    /// The backend is reachable but does not have the named model installed.
+    ModelNotFound(String),
+    /// The named model was still being loaded when the backend gave up waiting.
+    ModelLoading(String),
     InputError(String),
+    /// The user pressed CTRL-C at a prompt, while waiting for the AI or
+    /// while a command ran.
+    Interrupted,
+    /// The input reached its end, e.g. CTRL-D at a prompt.
+    EndOfInput,
 }
 
 impl fmt::Display for DiracError {
@@ -41,7 +234,12 @@ impl fmt::Display for DiracError {
         match self {
             DiracError::CommandExecutionError(msg) => write!(f, "Command execution error: {}", msg),
             DiracError::AIProcessingError(msg) => write!(f, "AI processing error: {}", msg),
+            DiracError::AIUnavailableError(msg) => write!(f, "AI service unavailable: {}", msg),
+            DiracError::ModelNotFound(model) => write!(f, "Model '{}' is not installed", model),
+            DiracError::ModelLoading(model) => write!(f, "Model '{}' is still loading", model),
             DiracError::InputError(msg) => write!(f, "Input error: {}", msg),
+            DiracError::Interrupted => write!(f, "Interrupted"),
+            DiracError::EndOfInput => write!(f, "End of input"),
         }
     }
 }
@@ -1,5 +1,13 @@
+pub mod context;
+pub mod conversation;
 pub mod lib;
+pub mod pipeline;
 pub mod plugin;
+pub mod suggestion;
 
-pub use self::lib::{AIProcessor, CommandExecutor, DiracError, PluginManager};
-pub use self::plugin::DefaultPluginManager;
\ No newline at end of file
+pub use self::context::ContextManager;
+pub use self::conversation::{Conversation, TurnOutcome};
+pub use self::lib::{AIProcessor, AIResponse, CommandExecutor, ContextProvider, DiracError, PluginManager, PromptKind};
+pub use self::pipeline::{Connector, Pipeline, Redirection, RiskFinding, SimpleCommand};
+pub use self::plugin::DefaultPluginManager;
+pub use self::suggestion::{Candidate, Probe, ProbeTool, Risk, Suggestion};
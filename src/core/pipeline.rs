@@ -0,0 +1,512 @@
+use crate::core::lib::{DiracError, DiracResult};
+use crate::core::suggestion::Risk;
+use std::fmt;
+
+/// How two commands of a pipeline are joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `|`: stdout feeds the next command
+    Pipe,
+    /// `|&`: stdout and stderr feed the next command
+    PipeAll,
+    /// `&&`: the next command runs if this one succeeds
+    And,
+    /// `||`: the next command runs if this one fails
+    Or,
+    /// `;`: the next command runs afterwards regardless
+    Sequence,
+    /// `&`: this command runs in the background
+    Background,
+}
+
+impl Connector {
+    fn from_operator(op: &str) -> Option<Self> {
+        match op {
+            "|" => Some(Connector::Pipe),
+            "|&" => Some(Connector::PipeAll),
+            "&&" => Some(Connector::And),
+            "||" => Some(Connector::Or),
+            ";" => Some(Connector::Sequence),
+            "&" => Some(Connector::Background),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Connector::Pipe => "|",
+            Connector::PipeAll => "|&",
+            Connector::And => "&&",
+            Connector::Or => "||",
+            Connector::Sequence => ";",
+            Connector::Background => "&",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Connector::Pipe => "pipe: the output of the previous command becomes the input of the next",
+            Connector::PipeAll => "pipe: the output and errors of the previous command become the input of the next",
+            Connector::And => "and: runs the next command only if the previous one succeeded",
+            Connector::Or => "or: runs the next command only if the previous one failed",
+            Connector::Sequence => "then: runs the next command after the previous one, whatever its result",
+            Connector::Background => "background: runs the previous command without waiting for it",
+        }
+    }
+}
+
+/// A redirection such as `> out.txt` or `2>&1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirection {
+    /// The operator including any file descriptor, e.g. `2>` or `>>`
+    pub operator: String,
+    pub target: String,
+}
+
+impl Redirection {
+    /// Whether the redirection replaces the contents of its target file.
+    pub fn overwrites(&self) -> bool {
+        let op = self.operator.trim_start_matches(|c: char| c.is_ascii_digit());
+        matches!(op, ">" | "&>" | ">|") && !self.target.starts_with('&') && self.target != "/dev/null"
+    }
+
+    pub fn description(&self) -> String {
+        let fd = self.operator.trim_end_matches(|c: char| !c.is_ascii_digit());
+        let op = &self.operator[fd.len()..];
+        let stream = match fd {
+            "" if op.starts_with('<') => "stdin",
+            "" | "1" => "stdout",
+            "2" => "stderr",
+            other => return format!("redirects file descriptor {} ({} {})", other, op, self.target),
+        };
+        match op {
+            ">" | ">|" if self.target == "/dev/null" => format!("discards {}", stream),
+            ">" | ">|" => format!("writes {} to {}, replacing its contents", stream, self.target),
+            ">>" => format!("appends {} to {}", stream, self.target),
+            ">&" => format!("sends {} to file descriptor {}", stream, self.target.trim_start_matches('&')),
+            "&>" if self.target == "/dev/null" => "discards stdout and stderr".to_string(),
+            "&>" => format!("writes stdout and stderr to {}, replacing its contents", self.target),
+            "&>>" => format!("appends stdout and stderr to {}", self.target),
+            "<" => format!("reads {} from {}", stream, self.target),
+            "<<" => format!("reads {} from the following lines, up to {}", stream, self.target),
+            "<<<" => format!("reads {} from the string {}", stream, self.target),
+            other => format!("redirection {} {}", other, self.target),
+        }
+    }
+}
+
+impl fmt::Display for Redirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operator.ends_with('&') {
+            write!(f, "{}{}", self.operator, self.target)
+        } else {
+            write!(f, "{} {}", self.operator, self.target)
+        }
+    }
+}
+
+/// One program invocation with its arguments and redirections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// Leading `NAME=value` environment assignments
+    pub assignments: Vec<String>,
+    pub program: Option<String>,
+    /// Arguments as written, quotes included
+    pub args: Vec<String>,
+    pub redirections: Vec<Redirection>,
+}
+
+/// Programs that run another command given as their arguments.
+const WRAPPERS: &[&str] = &["sudo", "doas", "env", "nohup", "time", "nice", "ionice", "xargs", "exec", "command", "stdbuf", "timeout"];
+
+impl SimpleCommand {
+    /// The program doing the actual work, looking through wrappers such as
+    /// `sudo` and `env`, with the arguments passed to it.
+    pub fn effective(&self) -> Option<(&str, &[String])> {
+        let mut program = self.program.as_deref()?;
+        let mut args = self.args.as_slice();
+        while WRAPPERS.contains(&program) {
+            // Options, `env` assignments and the duration of `timeout`
+            let is_wrapper_argument = |arg: &String| {
+                arg.starts_with('-')
+                    || arg.contains('=')
+                    || (program == "timeout" && arg.starts_with(|c: char| c.is_ascii_digit()))
+            };
+            let skip = args.iter().position(|arg| !is_wrapper_argument(arg)).unwrap_or(args.len());
+            match args.get(skip) {
+                Some(next) => {
+                    program = next;
+                    args = &args[skip + 1..];
+                }
+                None => break,
+            }
+        }
+        Some((program, args))
+    }
+
+    fn runs_as_root(&self) -> bool {
+        matches!(self.program.as_deref(), Some("sudo" | "doas"))
+    }
+}
+
+/// Commands joined by pipes and control operators, as parsed from one line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+    /// `connectors[i]` joins `commands[i]` and `commands[i + 1]`; a trailing
+    /// `&` or `;` has no command after it
+    pub connectors: Vec<Connector>,
+}
+
+/// Something in a command that deserves a warning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskFinding {
+    pub risk: Risk,
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Operator(String),
+}
+
+fn is_operator_char(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '<' | '>')
+}
+
+/// Splits a command line into words, quotes kept, and operators.
+fn tokenize(input: &str) -> DiracResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut word = String::new();
+    // Nesting of `$(...)` and backticks, inside which operators are literal
+    let mut depth = 0usize;
+    let mut in_backticks = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                word.push(c);
+                loop {
+                    match chars.next() {
+                        Some('\'') => {
+                            word.push('\'');
+                            break;
+                        }
+                        Some(c) => word.push(c),
+                        None => return Err(DiracError::InputError("Unterminated single quote".to_string())),
+                    }
+                }
+            }
+            '"' => {
+                word.push(c);
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            word.push('"');
+                            break;
+                        }
+                        Some('\\') => {
+                            word.push('\\');
+                            if let Some(escaped) = chars.next() {
+                                word.push(escaped);
+                            }
+                        }
+                        Some(c) => word.push(c),
+                        None => return Err(DiracError::InputError("Unterminated double quote".to_string())),
+                    }
+                }
+            }
+            '\\' => {
+                word.push(c);
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            '`' => {
+                in_backticks = !in_backticks;
+                word.push(c);
+            }
+            '$' if chars.peek() == Some(&'(') => {
+                word.push(c);
+                word.push(chars.next().unwrap_or('('));
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                word.push(c);
+            }
+            c if depth > 0 || in_backticks => word.push(c),
+            '#' if word.is_empty() => break,
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+            }
+            c if is_operator_char(c) => {
+                // A file descriptor number glued to a redirection, e.g. `2>`
+                let mut op = if !word.is_empty() && (c == '<' || c == '>') && word.chars().all(|c| c.is_ascii_digit()) {
+                    std::mem::take(&mut word)
+                } else {
+                    if !word.is_empty() {
+                        tokens.push(Token::Word(std::mem::take(&mut word)));
+                    }
+                    String::new()
+                };
+                op.push(c);
+                while let Some(&next) = chars.peek() {
+                    let candidate = format!("{}{}", op.trim_start_matches(|c: char| c.is_ascii_digit()), next);
+                    if matches!(candidate.as_str(), "||" | "&&" | "|&" | ">>" | "<<" | "<<<" | ">&" | "&>" | "&>>" | ">|" | "<&") {
+                        op.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Operator(op));
+            }
+            c => word.push(c),
+        }
+    }
+    if depth > 0 || in_backticks {
+        return Err(DiracError::InputError("Unterminated command substitution".to_string()));
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    Ok(tokens)
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// `-rf` and `-fr` both mean `-r -f`; long options are kept whole.
+fn has_short_flag(args: &[String], flag: char) -> bool {
+    args.iter().any(|arg| {
+        arg.starts_with('-') && !arg.starts_with("--") && arg[1..].chars().all(|c| c.is_ascii_alphanumeric()) && arg.contains(flag)
+    })
+}
+
+fn has_long_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)))
+}
+
+impl Pipeline {
+    /// Parses one line of shell input. Only the structure is recognized;
+    /// nothing is expanded or executed.
+    pub fn parse(input: &str) -> DiracResult<Self> {
+        let mut pipeline = Pipeline::default();
+        let mut current = SimpleCommand::default();
+        let mut tokens = tokenize(input)?.into_iter();
+
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Word(word) => {
+                    if current.program.is_none() && is_assignment(&word) {
+                        current.assignments.push(word);
+                    } else if current.program.is_none() {
+                        current.program = Some(word);
+                    } else {
+                        current.args.push(word);
+                    }
+                }
+                Token::Operator(op) => match Connector::from_operator(&op) {
+                    Some(connector) => {
+                        if current == SimpleCommand::default() {
+                            return Err(DiracError::InputError(format!("Nothing before '{}'", op)));
+                        }
+                        pipeline.commands.push(std::mem::take(&mut current));
+                        pipeline.connectors.push(connector);
+                    }
+                    None => {
+                        // `>&2` style operators carry their target along
+                        let (operator, target) = match op.strip_suffix('&') {
+                            Some(base) if !base.is_empty() && base != "&>" => match tokens.next() {
+                                Some(Token::Word(fd)) => (format!("{}&", base), fd),
+                                _ => return Err(DiracError::InputError(format!("Missing target after '{}'", op))),
+                            },
+                            _ => match tokens.next() {
+                                Some(Token::Word(target)) => (op, target),
+                                _ => return Err(DiracError::InputError(format!("Missing target after '{}'", op))),
+                            },
+                        };
+                        current.redirections.push(Redirection { operator, target });
+                    }
+                },
+            }
+        }
+
+        if current != SimpleCommand::default() {
+            pipeline.commands.push(current);
+        } else if matches!(pipeline.connectors.last(), Some(Connector::Pipe | Connector::PipeAll | Connector::And | Connector::Or)) {
+            return Err(DiracError::InputError("The command ends with an incomplete pipeline".to_string()));
+        }
+        if pipeline.commands.is_empty() {
+            return Err(DiracError::InputError("Nothing to explain".to_string()));
+        }
+        Ok(pipeline)
+    }
+
+    /// Patterns known to be destructive or hard to undo, most severe first.
+    /// The AI gets the final say; this only makes sure the obvious cases are
+    /// never missed.
+    pub fn risk_findings(&self) -> Vec<RiskFinding> {
+        let mut findings = Vec::new();
+        let mut flag = |risk: Risk, reason: String| findings.push(RiskFinding { risk, reason });
+
+        for (index, command) in self.commands.iter().enumerate() {
+            if command.runs_as_root() {
+                flag(Risk::Medium, "runs with root privileges".to_string());
+            }
+            for redirection in &command.redirections {
+                if redirection.overwrites() && std::path::Path::new(&redirection.target).exists() {
+                    flag(Risk::Medium, format!("overwrites the existing file {}", redirection.target));
+                }
+            }
+
+            let Some((program, args)) = command.effective() else {
+                continue;
+            };
+            let operands: Vec<&String> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+            let recursive = has_short_flag(args, 'r') || has_short_flag(args, 'R') || has_long_flag(args, "--recursive");
+            match program {
+                "rm" => {
+                    let broad = operands
+                        .iter()
+                        .any(|target| matches!(target.as_str(), "/" | "/*" | "~" | "~/" | "*" | "." | ".." | "$HOME"));
+                    if recursive && broad {
+                        flag(Risk::High, "recursively deletes a very broad target".to_string());
+                    } else if recursive {
+                        flag(Risk::High, "recursively deletes files and directories".to_string());
+                    } else {
+                        flag(Risk::Medium, "deletes files".to_string());
+                    }
+                }
+                "dd" | "mkfs" | "fdisk" | "sfdisk" | "parted" | "wipefs" | "shred" => {
+                    flag(Risk::High, format!("{} can irreversibly overwrite disks or files", program));
+                }
+                p if p.starts_with("mkfs.") => {
+                    flag(Risk::High, "formats a filesystem".to_string());
+                }
+                "shutdown" | "reboot" | "halt" | "poweroff" => {
+                    flag(Risk::High, "shuts down or restarts the machine".to_string());
+                }
+                "chmod" | "chown" | "chgrp" if recursive => {
+                    flag(Risk::Medium, format!("recursively changes ownership or permissions with {}", program));
+                }
+                "chmod" if operands.iter().any(|mode| mode.ends_with("777")) => {
+                    flag(Risk::Medium, "makes files writable by everyone".to_string());
+                }
+                "kill" | "killall" | "pkill" => {
+                    flag(Risk::Medium, "terminates processes".to_string());
+                }
+                "git" => {
+                    let sub = operands.first().map(|s| s.as_str());
+                    if sub == Some("push") && (has_short_flag(args, 'f') || has_long_flag(args, "--force")) {
+                        flag(Risk::High, "force-pushes, which can discard commits on the remote".to_string());
+                    } else if sub == Some("reset") && has_long_flag(args, "--hard") {
+                        flag(Risk::High, "discards uncommitted changes".to_string());
+                    } else if sub == Some("clean") && has_short_flag(args, 'f') {
+                        flag(Risk::High, "deletes untracked files".to_string());
+                    }
+                }
+                "sh" | "bash" | "zsh" | "python" | "python3" | "perl" | "ruby" | "node" if index > 0 => {
+                    let feeder = self.commands[index - 1].effective().map(|(p, _)| p);
+                    if matches!(self.connectors[index - 1], Connector::Pipe | Connector::PipeAll) && matches!(feeder, Some("curl" | "wget")) {
+                        flag(Risk::High, "runs a script downloaded from the internet without reviewing it".to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        findings.sort_by_key(|finding| std::cmp::Reverse(finding.risk));
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_splits_commands_at_connectors() {
+        let pipeline = Pipeline::parse("cat log | grep -i error && echo found; sleep 1 &").unwrap();
+        let programs: Vec<Option<&str>> = pipeline.commands.iter().map(|command| command.program.as_deref()).collect();
+        assert_eq!(programs, [Some("cat"), Some("grep"), Some("echo"), Some("sleep")]);
+        assert_eq!(pipeline.commands[1].args, words(&["-i", "error"]));
+        assert_eq!(
+            pipeline.connectors,
+            [Connector::Pipe, Connector::And, Connector::Sequence, Connector::Background]
+        );
+    }
+
+    #[test]
+    fn parse_keeps_quotes_and_substitutions_whole() {
+        let pipeline = Pipeline::parse(r#"echo 'a | b' "c && d" $(date | cut -c1-3) `ls;pwd` # comment"#).unwrap();
+        assert_eq!(pipeline.commands.len(), 1);
+        assert_eq!(
+            pipeline.commands[0].args,
+            words(&["'a | b'", r#""c && d""#, "$(date | cut -c1-3)", "`ls;pwd`"])
+        );
+    }
+
+    #[test]
+    fn parse_reads_assignments_and_redirections() {
+        let pipeline = Pipeline::parse("LANG=C make all > build.log 2>&1").unwrap();
+        let command = &pipeline.commands[0];
+        assert_eq!(command.assignments, words(&["LANG=C"]));
+        assert_eq!(command.program.as_deref(), Some("make"));
+        assert_eq!(command.args, words(&["all"]));
+        assert_eq!(
+            command.redirections,
+            [
+                Redirection { operator: ">".to_string(), target: "build.log".to_string() },
+                Redirection { operator: "2>&".to_string(), target: "1".to_string() },
+            ]
+        );
+        assert_eq!(command.redirections[1].to_string(), "2>&1");
+        assert_eq!(command.redirections[1].description(), "sends stderr to file descriptor 1");
+    }
+
+    #[test]
+    fn parse_rejects_incomplete_input() {
+        for input in ["", "   ", "| grep x", "ls |", "ls &&", "echo 'open", "echo \"open", "echo $(date", "cat <"] {
+            assert!(Pipeline::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn effective_looks_through_wrappers() {
+        let pipeline = Pipeline::parse("sudo -E env FOO=1 timeout 5 rm -rf build").unwrap();
+        let (program, args) = pipeline.commands[0].effective().unwrap();
+        assert_eq!(program, "rm");
+        assert_eq!(args, words(&["-rf", "build"]).as_slice());
+    }
+
+    #[test]
+    fn risk_findings_flag_destructive_commands() {
+        let findings = Pipeline::parse("sudo rm -rf /").unwrap().risk_findings();
+        assert_eq!(findings[0].risk, Risk::High);
+        assert_eq!(findings[0].reason, "recursively deletes a very broad target");
+        assert!(findings.iter().any(|finding| finding.reason == "runs with root privileges"));
+
+        let findings = Pipeline::parse("curl -s https://example.com/install.sh | sh").unwrap().risk_findings();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].risk, Risk::High);
+
+        assert!(Pipeline::parse("ls -la | wc -l").unwrap().risk_findings().is_empty());
+    }
+}
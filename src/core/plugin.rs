@@ -13,13 +13,19 @@ impl DefaultPluginManager {
     }
 }
 
+impl Default for DefaultPluginManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PluginManager for DefaultPluginManager {
     fn register_plugin(&mut self, plugin: Box<dyn Plugin>) {
         self.plugins.push(plugin);
     }
 
-    fn get_plugin(&self, name: &str) -> Option<&Box<dyn Plugin>> {
-        self.plugins.iter().find(|p| p.name() == name)
+    fn get_plugin(&self, name: &str) -> Option<&dyn Plugin> {
+        self.plugins.iter().find(|p| p.name() == name).map(|p| p.as_ref())
     }
 
     fn list_plugins(&self) -> Vec<(&str, &str)> {
@@ -44,6 +50,12 @@ impl HistoryPlugin {
     }
 }
 
+impl Default for HistoryPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Plugin for HistoryPlugin {
     fn name(&self) -> &str {
         "history"
@@ -0,0 +1,312 @@
+use crate::core::lib::{DiracError, DiracResult, PromptKind};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Risk::Low => write!(f, "low"),
+            Risk::Medium => write!(f, "medium"),
+            Risk::High => write!(f, "high"),
+        }
+    }
+}
+
+/// Most alternatives the model is asked for besides its top suggestion.
+pub const MAX_ALTERNATIVES: usize = 2;
+
+/// Most steps of a plan.
+pub const MAX_PLAN_STEPS: usize = 10;
+
+/// Most read-only probes the model may request in one answer.
+pub const MAX_PROBES: usize = 4;
+
+/// Read-only inspections the model can ask for before answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeTool {
+    /// Entries of a directory
+    ListDir,
+    /// Type, size, permissions and modification time of a path
+    Stat,
+    /// First lines of a text file
+    Head,
+    /// Location of a program on the PATH
+    Which,
+    /// Output of `<program> --help`
+    Help,
+}
+
+impl ProbeTool {
+    pub const ALL: [ProbeTool; 5] = [
+        ProbeTool::ListDir,
+        ProbeTool::Stat,
+        ProbeTool::Head,
+        ProbeTool::Which,
+        ProbeTool::Help,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProbeTool::ListDir => "list_dir",
+            ProbeTool::Stat => "stat",
+            ProbeTool::Head => "head",
+            ProbeTool::Which => "which",
+            ProbeTool::Help => "help",
+        }
+    }
+}
+
+/// A read-only inspection requested by the model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probe {
+    pub tool: ProbeTool,
+    pub argument: String,
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.tool.name(), self.argument)
+    }
+}
+
+/// One ranked way of fulfilling a request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub command: String,
+    pub explanation: String,
+    pub risk: Risk,
+}
+
+/// A command proposed by the model, as requested through a JSON schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub command: String,
+    pub explanation: String,
+    pub risk: Risk,
+    /// Other interpretations, ranked from most to least suitable
+    #[serde(default)]
+    pub alternatives: Vec<Candidate>,
+    #[serde(default)]
+    pub requires_sudo: bool,
+    /// Inspections to run and report back before the model commits to a
+    /// command; `command` is empty while any are requested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probes: Vec<Probe>,
+    /// Commands to run one after another, for plans; `command` is empty
+    /// and `explanation` summarizes the plan
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<Candidate>,
+}
+
+impl Suggestion {
+    /// JSON schema handed to backends that support constrained output.
+    /// Plans additionally carry their steps.
+    pub fn schema(kind: PromptKind) -> Value {
+        let candidate = json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string" },
+                "explanation": { "type": "string" },
+                "risk": { "type": "string", "enum": ["low", "medium", "high"] }
+            },
+            "required": ["command", "explanation", "risk"]
+        });
+        let mut schema = json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string" },
+                "explanation": { "type": "string" },
+                "risk": { "type": "string", "enum": ["low", "medium", "high"] },
+                "alternatives": {
+                    "type": "array",
+                    "maxItems": MAX_ALTERNATIVES,
+                    "items": candidate
+                },
+                "requires_sudo": { "type": "boolean" },
+                "probes": {
+                    "type": "array",
+                    "maxItems": MAX_PROBES,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "tool": {
+                                "type": "string",
+                                "enum": ProbeTool::ALL.map(ProbeTool::name)
+                            },
+                            "argument": { "type": "string" }
+                        },
+                        "required": ["tool", "argument"]
+                    }
+                }
+            },
+            "required": ["command", "explanation", "risk", "alternatives", "requires_sudo", "probes"]
+        });
+        if kind == PromptKind::Plan {
+            schema["properties"]["steps"] = json!({
+                "type": "array",
+                "minItems": 1,
+                "maxItems": MAX_PLAN_STEPS,
+                "items": candidate
+            });
+            if let Some(required) = schema["required"].as_array_mut() {
+                required.push(json!("steps"));
+            }
+        }
+        schema
+    }
+
+    /// Parses and validates a model answer. Code fences and stray text around
+    /// the JSON object are tolerated for servers that ignore the schema.
+    pub fn parse(text: &str) -> DiracResult<Self> {
+        let suggestion = Self::parse_answer(text)?;
+        if suggestion.command.is_empty() && suggestion.probes.is_empty() && suggestion.steps.is_empty() {
+            return Err(DiracError::AIProcessingError(
+                "The model returned an empty command.".to_string(),
+            ));
+        }
+        Ok(suggestion)
+    }
+
+    /// Like `parse`, but accepts an answer without any command, as answers
+    /// to `ask` may be prose only.
+    pub fn parse_answer(text: &str) -> DiracResult<Self> {
+        let text = text.trim();
+        let json_text = match (text.find('{'), text.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &text[start..=end],
+            _ => {
+                return Err(DiracError::AIProcessingError(
+                    "The model did not return a JSON suggestion.".to_string(),
+                ))
+            }
+        };
+
+        let mut suggestion: Suggestion = serde_json::from_str(json_text).map_err(|e| {
+            DiracError::AIProcessingError(format!("The model returned an invalid suggestion: {}", e))
+        })?;
+
+        suggestion.command = suggestion.command.trim().to_string();
+        suggestion.explanation = suggestion.explanation.trim().to_string();
+        for alternative in &mut suggestion.alternatives {
+            alternative.command = alternative.command.trim().to_string();
+            alternative.explanation = alternative.explanation.trim().to_string();
+        }
+        let primary = suggestion.command.clone();
+        suggestion.alternatives.retain(|alternative| {
+            !alternative.command.is_empty() && alternative.command != primary
+        });
+        suggestion.alternatives.truncate(MAX_ALTERNATIVES);
+        for probe in &mut suggestion.probes {
+            probe.argument = probe.argument.trim().to_string();
+        }
+        suggestion.probes.dedup();
+        suggestion.probes.truncate(MAX_PROBES);
+        for step in &mut suggestion.steps {
+            step.command = step.command.trim().to_string();
+            step.explanation = step.explanation.trim().to_string();
+        }
+        suggestion.steps.retain(|step| !step.command.is_empty());
+        suggestion.steps.truncate(MAX_PLAN_STEPS);
+        Ok(suggestion)
+    }
+
+    /// All candidates in rank order, the top suggestion first.
+    pub fn candidates(&self) -> Vec<Candidate> {
+        let primary = Candidate {
+            command: self.command.clone(),
+            explanation: self.explanation.clone(),
+            risk: self.risk,
+        };
+        std::iter::once(primary)
+            .chain(self.alternatives.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_a_plain_answer() {
+        let suggestion = Suggestion::parse(
+            r#"{"command": " ls -la ", "explanation": "Lists files ", "risk": "low", "alternatives": [], "requires_sudo": false, "probes": []}"#,
+        )
+        .unwrap();
+        assert_eq!(suggestion.command, "ls -la");
+        assert_eq!(suggestion.explanation, "Lists files");
+        assert_eq!(suggestion.risk, Risk::Low);
+        assert!(!suggestion.requires_sudo);
+    }
+
+    #[test]
+    fn parse_tolerates_fences_and_surrounding_text() {
+        let text = "Sure, here it is:\n```json\n{\"command\": \"df -h\", \"explanation\": \"Disk usage\", \"risk\": \"low\"}\n```\nHope that helps.";
+        let suggestion = Suggestion::parse(text).unwrap();
+        assert_eq!(suggestion.command, "df -h");
+        assert!(suggestion.alternatives.is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_answers_without_a_command() {
+        assert!(Suggestion::parse("I cannot help with that.").is_err());
+        assert!(Suggestion::parse(r#"{"command": "ls"}"#).is_err());
+        assert!(Suggestion::parse(r#"{"command": "  ", "explanation": "", "risk": "low"}"#).is_err());
+        assert!(Suggestion::parse(r#"{"command": "ls", "explanation": "", "risk": "extreme"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_answer_accepts_prose_only_answers() {
+        let suggestion = Suggestion::parse_answer(r#"{"command": "", "explanation": "The build failed on a missing crate.", "risk": "low"}"#).unwrap();
+        assert!(suggestion.command.is_empty());
+        assert_eq!(suggestion.explanation, "The build failed on a missing crate.");
+    }
+
+    #[test]
+    fn parse_cleans_up_alternatives() {
+        let suggestion = Suggestion::parse(
+            r#"{"command": "rm -r build", "explanation": "", "risk": "medium", "alternatives": [
+                {"command": "rm -r build", "explanation": "same", "risk": "medium"},
+                {"command": "  ", "explanation": "empty", "risk": "low"},
+                {"command": "cargo clean", "explanation": "via cargo", "risk": "low"},
+                {"command": "trash build", "explanation": "recoverable", "risk": "low"},
+                {"command": "mv build /tmp", "explanation": "moved", "risk": "low"}
+            ]}"#,
+        )
+        .unwrap();
+        let commands: Vec<String> = suggestion.candidates().into_iter().map(|candidate| candidate.command).collect();
+        assert_eq!(commands, ["rm -r build", "cargo clean", "trash build"]);
+    }
+
+    #[test]
+    fn parse_accepts_probes_and_plan_steps_instead_of_a_command() {
+        let suggestion = Suggestion::parse(
+            r#"{"command": "", "explanation": "", "risk": "low", "probes": [
+                {"tool": "list_dir", "argument": " src "},
+                {"tool": "list_dir", "argument": "src"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(suggestion.probes, [Probe { tool: ProbeTool::ListDir, argument: "src".to_string() }]);
+
+        let plan = Suggestion::parse(
+            r#"{"command": "", "explanation": "Build then test", "risk": "low", "steps": [
+                {"command": "cargo build", "explanation": "", "risk": "low"},
+                {"command": "", "explanation": "nothing", "risk": "low"},
+                {"command": "cargo test", "explanation": "", "risk": "low"}
+            ]}"#,
+        )
+        .unwrap();
+        let steps: Vec<&str> = plan.steps.iter().map(|step| step.command.as_str()).collect();
+        assert_eq!(steps, ["cargo build", "cargo test"]);
+    }
+}
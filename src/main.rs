@@ -1,19 +1,279 @@
-mod core;
-mod services;
-mod ui;
+use clap::{Parser, ValueEnum};
+use dirac_terminal::core::AIProcessor;
+use dirac_terminal::services::{
+    ai, cache, listing, openai, CachedProcessor, OllamaModels, OllamaProcessor, OpenAIProcessor,
+    PromptTemplates, RouterProcessor,
+};
+use dirac_terminal::services::request::RequestPolicy;
+#[cfg(feature = "embedded")]
+use dirac_terminal::services::{embedded, EmbeddedProcessor};
+use dirac_terminal::ui::terminal::{DiracTerminal, DEFAULT_PROBE_ROUNDS};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::Parser;
-use crate::ui::terminal::DiracTerminal;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    /// Local Ollama server (`/api/chat`)
+    Ollama,
+    /// Any server speaking the OpenAI `/v1/chat/completions` protocol
+    Openai,
+    /// A GGUF model file run in-process on the CPU, without a server
+    #[cfg(feature = "embedded")]
+    Embedded,
+}
 
 #[derive(Parser)]
 #[command(name = "dirac")]
 #[command(about = "AI-powered terminal that understands natural language")]
-struct Cli {}
+struct Cli {
+    /// AI backend used to generate commands
+    #[arg(long, value_enum, default_value_t = Backend::Ollama)]
+    backend: Backend,
+
+    /// Model name, defaults to the backend's default model. For the embedded
+    /// backend, a GGUF file or the name of one in `~/.local/share/dirac/models`
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Endpoint URL, defaults to the backend's local default
+    #[arg(long)]
+    api_url: Option<String>,
+
+    /// API key for OpenAI-compatible servers, falls back to OPENAI_API_KEY
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Provider to try when the previous ones are unreachable, as
+    /// `backend:model[@url]`, e.g. `ollama:llama3.2:1b`. May be repeated.
+    #[arg(long = "fallback", value_name = "SPEC", value_parser = parse_provider)]
+    fallbacks: Vec<ProviderSpec>,
+
+    /// Always ask the model instead of reusing cached answers
+    #[arg(long)]
+    no_cache: bool,
+
+    /// How long cached answers are reused, in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = cache::DEFAULT_TTL.as_secs())]
+    cache_ttl: u64,
+
+    /// Directory with prompt templates (generate.jinja, diagnose.jinja,
+    /// explain.jinja, plan.jinja, ask.jinja), defaults to `~/.config/dirac/prompts`
+    #[arg(long, value_name = "DIR")]
+    prompt_dir: Option<PathBuf>,
+
+    /// Most files and directories named in the directory summary sent to the AI
+    #[arg(long, value_name = "N", default_value_t = listing::DEFAULT_MAX_ENTRIES)]
+    max_dir_entries: usize,
+
+    /// Context provider to leave out of AI requests, e.g. `git`. May be repeated.
+    #[arg(long = "disable-context", value_name = "PROVIDER")]
+    disabled_context: Vec<String>,
+
+    /// Size limit of a context provider's section, e.g. `git=300`. May be repeated.
+    #[arg(long = "context-budget", value_name = "PROVIDER=CHARS", value_parser = parse_budget)]
+    context_budgets: Vec<(String, usize)>,
+
+    /// Rounds of read-only probes (list_dir, stat, head, which, --help) the
+    /// model may run before answering; 0 disables them
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_PROBE_ROUNDS)]
+    max_probe_rounds: usize,
+
+    #[command(flatten)]
+    requests: RequestArgs,
+}
+
+/// A setting for every backend, or for one of them when prefixed with
+/// `backend=`, e.g. `30` or `openai=30`.
+#[derive(Clone, Debug)]
+struct BackendSetting<T> {
+    backend: Option<Backend>,
+    value: T,
+}
+
+fn parse_backend_setting<T: FromStr>(spec: &str) -> Result<BackendSetting<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    let (backend, value) = match spec.split_once('=') {
+        Some((backend, value)) => (Some(Backend::from_str(backend, true)?), value),
+        None => (None, spec),
+    };
+    let value = value
+        .parse()
+        .map_err(|e| format!("invalid value '{}': {}", value, e))?;
+    Ok(BackendSetting { backend, value })
+}
+
+/// Timeouts and retries of AI requests. Each flag may be repeated, and a
+/// setting for one backend takes precedence over one for all of them.
+#[derive(clap::Args)]
+struct RequestArgs {
+    /// Seconds a connection to the AI server may take
+    #[arg(long, value_name = "[BACKEND=]SECONDS", value_parser = parse_backend_setting::<u64>)]
+    connect_timeout: Vec<BackendSetting<u64>>,
+
+    /// Seconds the AI server may take to start answering, including loading the model
+    #[arg(long, value_name = "[BACKEND=]SECONDS", value_parser = parse_backend_setting::<u64>)]
+    load_timeout: Vec<BackendSetting<u64>>,
+
+    /// Seconds a streamed answer may stall
+    #[arg(long, value_name = "[BACKEND=]SECONDS", value_parser = parse_backend_setting::<u64>)]
+    read_timeout: Vec<BackendSetting<u64>>,
+
+    /// Retries of requests that failed transiently, e.g. with 503
+    #[arg(long, value_name = "[BACKEND=]N", value_parser = parse_backend_setting::<u32>)]
+    max_retries: Vec<BackendSetting<u32>>,
+}
+
+impl RequestArgs {
+    fn setting<T: Copy>(settings: &[BackendSetting<T>], backend: Backend) -> Option<T> {
+        let specific = settings.iter().rev().find(|setting| setting.backend == Some(backend));
+        let general = settings.iter().rev().find(|setting| setting.backend.is_none());
+        specific.or(general).map(|setting| setting.value)
+    }
+
+    fn policy(&self, backend: Backend) -> RequestPolicy {
+        let defaults = RequestPolicy::default();
+        let seconds = |settings: &[BackendSetting<u64>], default: Duration| {
+            Self::setting(settings, backend).map(Duration::from_secs).unwrap_or(default)
+        };
+        RequestPolicy {
+            connect_timeout: seconds(&self.connect_timeout, defaults.connect_timeout),
+            load_timeout: seconds(&self.load_timeout, defaults.load_timeout),
+            read_timeout: seconds(&self.read_timeout, defaults.read_timeout),
+            max_retries: Self::setting(&self.max_retries, backend).unwrap_or(defaults.max_retries),
+            ..defaults
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ProviderSpec {
+    backend: Backend,
+    model: Option<String>,
+    api_url: Option<String>,
+}
+
+fn parse_provider(spec: &str) -> Result<ProviderSpec, String> {
+    let (provider, api_url) = match spec.split_once('@') {
+        Some((provider, api_url)) => (provider, Some(api_url.to_string())),
+        None => (spec, None),
+    };
+    let (backend, model) = match provider.split_once(':') {
+        Some((backend, model)) => (backend, Some(model.to_string())),
+        None => (provider, None),
+    };
+    let backend = Backend::from_str(backend, true)?;
+    Ok(ProviderSpec { backend, model, api_url })
+}
+
+fn parse_budget(spec: &str) -> Result<(String, usize), String> {
+    let (name, chars) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected PROVIDER=CHARS, got '{}'", spec))?;
+    let chars = chars
+        .parse()
+        .map_err(|e| format!("invalid size '{}': {}", chars, e))?;
+    Ok((name.to_string(), chars))
+}
+
+fn build_provider(spec: ProviderSpec, api_key: Option<String>, templates: Arc<PromptTemplates>, requests: &RequestArgs) -> Box<dyn AIProcessor> {
+    let policy = requests.policy(spec.backend);
+    match spec.backend {
+        Backend::Ollama => Box::new(
+            OllamaProcessor::new(
+                spec.model.unwrap_or_else(|| ai::DEFAULT_MODEL.to_string()),
+                spec.api_url.unwrap_or_else(|| ai::DEFAULT_API_URL.to_string()),
+                templates,
+            )
+            .with_policy(policy),
+        ),
+        Backend::Openai => Box::new(
+            OpenAIProcessor::new(
+                spec.model.unwrap_or_else(|| openai::DEFAULT_MODEL.to_string()),
+                spec.api_url.unwrap_or_else(|| openai::DEFAULT_API_URL.to_string()),
+                api_key,
+                templates,
+            )
+            .with_policy(policy),
+        ),
+        #[cfg(feature = "embedded")]
+        Backend::Embedded => Box::new(EmbeddedProcessor::new(
+            embedded::model_path(spec.model.as_deref()),
+            templates,
+        )),
+    }
+}
+
+fn build_processor(cli: Cli, templates: Arc<PromptTemplates>) -> Box<dyn AIProcessor> {
+    let api_key = cli.api_key.or_else(|| std::env::var("OPENAI_API_KEY").ok());
+    let primary = ProviderSpec {
+        backend: cli.backend,
+        model: cli.model,
+        api_url: cli.api_url,
+    };
+    if cli.fallbacks.is_empty() {
+        return build_provider(primary, api_key, templates, &cli.requests);
+    }
+
+    let providers = std::iter::once(primary)
+        .chain(cli.fallbacks)
+        .map(|spec| build_provider(spec, api_key.clone(), Arc::clone(&templates), &cli.requests))
+        .collect();
+    Box::new(RouterProcessor::new(providers))
+}
 
 #[tokio::main]
 async fn main() {
-    let _cli = Cli::parse();
-    let mut terminal = DiracTerminal::new();
-    
+    let mut cli = Cli::parse();
+    let no_cache = cli.no_cache;
+    let cache_ttl = Duration::from_secs(cli.cache_ttl);
+    let disabled_context = std::mem::take(&mut cli.disabled_context);
+    let context_budgets = std::mem::take(&mut cli.context_budgets);
+    let max_probe_rounds = cli.max_probe_rounds;
+    // Model management talks to the primary provider's server
+    let models = match cli.backend {
+        Backend::Ollama => Some(
+            OllamaModels::new(cli.api_url.as_deref().unwrap_or(ai::DEFAULT_API_URL))
+                .with_policy(cli.requests.policy(Backend::Ollama)),
+        ),
+        _ => None,
+    };
+    let templates = match cli.prompt_dir.clone().or_else(PromptTemplates::default_dir) {
+        Some(dir) => PromptTemplates::load(&dir),
+        None => Ok(PromptTemplates::builtin()),
+    };
+    let templates = match templates {
+        Ok(templates) => Arc::new(templates.with_max_dir_entries(cli.max_dir_entries)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut processor = build_processor(cli, templates);
+    if !no_cache {
+        processor = Box::new(CachedProcessor::with_default_path(processor, cache_ttl));
+    }
+    let mut terminal = DiracTerminal::new(processor);
+    terminal.set_max_probe_rounds(max_probe_rounds);
+    if let Some(models) = models {
+        terminal.set_model_manager(models);
+    }
+    let context = terminal.context_mut();
+    let configured = disabled_context
+        .iter()
+        .try_for_each(|name| context.set_enabled(name, false))
+        .and_then(|_| {
+            context_budgets
+                .iter()
+                .try_for_each(|(name, chars)| context.set_budget(name, *chars))
+        });
+    if let Err(e) = configured {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
     terminal.run().await;
 }
@@ -1,173 +1,337 @@
-use crate::core::lib::{AIProcessor, DiracError, DiracResult};
+use crate::core::lib::{AIProcessor, AIResponse, ChatMessage, ChatRole, DiracError, DiracResult, PromptKind};
+use crate::core::suggestion::Suggestion;
+use crate::services::models::OllamaModels;
+use crate::services::prompt::PromptTemplates;
+use crate::services::request::{RequestPolicy, SendFailure};
+use futures_util::{future, stream, Stream, StreamExt};
 use reqwest::Client;
 use serde_json::{json, Value};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::timeout;
+
+pub const DEFAULT_MODEL: &str = "qwen2.5:3b";
+pub const DEFAULT_API_URL: &str = "http://localhost:11434/api/chat";
 
 #[derive(Debug)]
 pub struct OllamaProcessor {
     client: Client,
-    model: String,
+    policy: RequestPolicy,
+    /// Switched at runtime by the `model use` builtin
+    model: RwLock<String>,
     api_url: String,
+    templates: Arc<PromptTemplates>,
+    /// Tells whether the model is in memory yet
+    models: OllamaModels,
 }
 
 impl OllamaProcessor {
-    pub fn new(model: impl Into<String>, api_url: impl Into<String>) -> Self {
+    pub fn new(model: impl Into<String>, api_url: impl Into<String>, templates: Arc<PromptTemplates>) -> Self {
+        let api_url = api_url.into();
+        let policy = RequestPolicy::default();
         Self {
-            client: Client::new(),
-            model: model.into(),
-            api_url: api_url.into(),
+            client: policy.client(),
+            policy,
+            model: RwLock::new(model.into()),
+            models: OllamaModels::new(&api_url),
+            api_url,
+            templates,
         }
     }
 
-    pub fn with_default_config() -> Self {
-        Self::new(
-            "qwen2.5:3b",
-            "http://localhost:11434/api/generate",
+    /// Uses `policy` for timeouts and retries instead of the defaults.
+    pub fn with_policy(mut self, policy: RequestPolicy) -> Self {
+        self.client = policy.client();
+        self.models = self.models.with_policy(policy.clone());
+        self.policy = policy;
+        self
+    }
+
+    async fn send(&self, body: &Value, model: &str) -> DiracResult<reqwest::Response> {
+        self.policy
+            .send(|| self.client.post(&self.api_url).json(body))
+            .await
+            .map_err(|failure| send_error(failure, model))
+    }
+
+    fn current_model(&self) -> String {
+        self.model.read().map(|model| model.clone()).unwrap_or_default()
+    }
+}
+
+/// Appends the prompt for the current request to the earlier turns of the session.
+pub(crate) fn chat_messages(prompt: String, history: &[ChatMessage]) -> Vec<ChatMessage> {
+    let mut messages = history.to_vec();
+    messages.push(ChatMessage::new(ChatRole::User, prompt));
+    messages
+}
+
+/// Splits a streamed HTTP body into trimmed lines, as used by both Ollama's
+/// NDJSON stream and the server-sent events of OpenAI-compatible servers.
+/// The stream fails when the body stalls for longer than `read_timeout`.
+pub(crate) fn response_lines(response: reqwest::Response, read_timeout: Duration) -> impl Stream<Item = DiracResult<String>> + Send {
+    body_lines(response.bytes_stream(), read_timeout)
+}
+
+/// `response_lines` for any stream of byte chunks.
+fn body_lines<S, B, E>(body: S, read_timeout: Duration) -> impl Stream<Item = DiracResult<String>> + Send
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send,
+    E: std::fmt::Display + Send,
+{
+    let bytes = Box::pin(body);
+    stream::unfold((bytes, Vec::new(), false), move |(mut bytes, mut buffer, mut finished)| async move {
+        loop {
+            if let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                return Some((Ok(line), (bytes, buffer, finished)));
+            }
+            if finished {
+                if buffer.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8_lossy(&buffer).trim().to_string();
+                buffer.clear();
+                return Some((Ok(line), (bytes, buffer, finished)));
+            }
+            let next = match timeout(read_timeout, bytes.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    buffer.clear();
+                    let error = DiracError::AIProcessingError(format!(
+                        "The AI service stopped responding for {} seconds",
+                        read_timeout.as_secs()
+                    ));
+                    return Some((Err(error), (bytes, buffer, true)));
+                }
+            };
+            match next {
+                Some(Ok(chunk)) => buffer.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => {
+                    buffer.clear();
+                    let error = DiracError::AIProcessingError(format!("Failed to read AI response: {}", e));
+                    return Some((Err(error), (bytes, buffer, true)));
+                }
+                None => finished = true,
+            }
+        }
+    })
+}
+
+/// Maps a request that failed for good to the error shown to the user.
+pub(crate) fn send_error(failure: SendFailure, model: &str) -> DiracError {
+    match failure {
+        SendFailure::Request(e) => request_error(e, model),
+        SendFailure::TimedOut(limit) => DiracError::AIUnavailableError(format!(
+            "Ollama did not answer within {} seconds. Please check if the service is responding.",
+            limit.as_secs()
+        )),
+        SendFailure::Status { status, body } => {
+            let message = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|body| body.get("error")?.as_str().map(str::to_string))
+                .unwrap_or(body);
+            DiracError::AIUnavailableError(format!("Ollama is busy ({}): {}", status, message.trim()))
+        }
+        SendFailure::Loading => DiracError::ModelLoading(model.to_string()),
+    }
+}
+
+fn request_error(e: reqwest::Error, model: &str) -> DiracError {
+    if e.is_connect() && !e.is_timeout() {
+        DiracError::AIUnavailableError(
+            "Ollama service is not running. To install and start Ollama:\n".to_string() +
+            "1. Visit https://ollama.ai to download and install Ollama\n" +
+            "2. Start the Ollama service\n" +
+            format!("3. Run 'ollama pull {}' to download the model", model).as_str()
         )
+    } else if e.is_timeout() {
+        DiracError::AIUnavailableError("Connection to Ollama service timed out. Please check if the service is responding.".to_string())
+    } else {
+        DiracError::AIProcessingError(format!("Failed to connect to AI service: {}", e))
+    }
+}
+
+/// Whether an Ollama error says the model is not installed, as in
+/// `model "x" not found, try pulling it first` or `model 'x' not found`.
+fn is_model_not_found(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.starts_with("model ") && message.contains(" not found")
+}
+
+pub(crate) fn api_error(error: &Value, model: &str) -> DiracError {
+    let error_msg = error.as_str().unwrap_or("Unknown error");
+    if is_model_not_found(error_msg) {
+        return DiracError::ModelNotFound(model.to_string());
     }
+    DiracError::AIProcessingError(format!("Ollama error: {}", error_msg))
 }
 
 #[async_trait::async_trait]
 impl AIProcessor for OllamaProcessor {
-    async fn process<'a>(&'a self, input: &'a str, context: &'a str) -> DiracResult<String> {
-        let current_dir = std::env::current_dir().unwrap_or_default().display().to_string();
-        let os_type = if cfg!(target_os = "windows") {
-            "windows"
-        } else if cfg!(target_os = "macos") {
-            "macos"
-        } else {
-            "linux"
-        };
-        let directory_structure = std::fs::read_dir(".").map(|entries| {
-            entries
-                .filter_map(|e| e.ok())
-                .map(|e| format!("  {}", e.file_name().to_string_lossy()))
-                .collect::<Vec<_>>()
-                .join("\n")
-        }).unwrap_or_default();
-
-        // Improved system prompt:
-        let prompt = format!(
-            "You are a sophisticated terminal command generator that converts natural language requests into precise, executable shell commands.
-When provided with a user request and additional context, you must:
-  
-1. **Ensure Accuracy and Safety**:
-   - Generate commands that can be executed directly without any modifications.
-   - Prioritize safe, non-destructive commands (e.g., 'ls', 'pwd') when the request is ambiguous.
-   - Convert natural language navigation requests (such as 'go to', 'open', 'change to') into the appropriate 'cd' commands.
-
-2. **Detect and Correct Typos**:
-   - Identify any typographical errors (for example, convert 'lsbkk' to 'ls').
-   - If a correction is made or multiple interpretations are possible, include clear guidance in the explanation.
-
-3. **Leverage Context**:
-   - Use the provided details about the current working directory, operating system, and directory structure to tailor your response.
-   - Ensure that any suggested navigation or file-related commands reflect the actual environment.
-
-4. **Follow the Strict Response Format**:
-   - Your answer must be in the exact format shown below with no extra text:
-     
-     COMMAND: <the exact command to execute>
-     EXPLANATION: <a concise explanation of the command, including any corrections or alternative suggestions>
-
-**Input Details**:
-- User Request: '{}'
-- Additional Context: '{}'
-- Current Environment:
-   - Working Directory: {}
-   - OS Type: {}
-   - Directory Structure:
-{}
-
-Based on these details, generate the appropriate terminal command and a brief explanation.",
-            input,
-            context,
-            current_dir,
-            os_type,
-            directory_structure
-        );
-
-        let response = self
-            .client
-            .post(&self.api_url)
-            .json(&json!({
-                "model": self.model,
-                "prompt": prompt,
-                "stream": false
-            }))
-            .send()
-            .await
-            .map_err(|e| {
-                if e.is_connect() {
-                    DiracError::AIProcessingError(
-                        "Ollama service is not running. To install and start Ollama:\n".to_string() +
-                        "1. Visit https://ollama.ai to download and install Ollama\n" +
-                        "2. Start the Ollama service\n" +
-                        "3. Run 'ollama pull qwen2.5:3b' to download the model"
-                    )
-                } else if e.is_timeout() {
-                    DiracError::AIProcessingError("Connection to Ollama service timed out. Please check if the service is responding.".to_string())
-                } else {
-                    DiracError::AIProcessingError(format!("Failed to connect to AI service: {}", e))
-                }
-            })?;
+    fn name(&self) -> String {
+        format!("ollama:{}", self.current_model())
+    }
+
+    fn model(&self) -> Option<String> {
+        Some(self.current_model())
+    }
+
+    async fn is_model_loaded(&self) -> Option<bool> {
+        self.models.is_loaded(&self.current_model()).await.ok()
+    }
+
+    fn set_model(&self, model: &str) -> DiracResult<()> {
+        let mut current = self
+            .model
+            .write()
+            .map_err(|_| DiracError::AIProcessingError("The model setting is unavailable".to_string()))?;
+        *current = model.to_string();
+        Ok(())
+    }
+
+    async fn process<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<Suggestion> {
+        let prompt = self.templates.render(kind, input, context, history)?;
+        let messages = chat_messages(prompt, history);
+        let model = self.current_model();
+
+        let body = json!({
+            "model": model,
+            "messages": messages,
+            "format": Suggestion::schema(kind),
+            "stream": false
+        });
+        let response = self.send(&body, &model).await?;
 
         let text = response
             .text()
             .await
             .map_err(|e| DiracError::AIProcessingError(format!("Failed to read AI response: {}", e)))?;
 
-        // Try to parse the response as JSON to check for error messages
-        if let Ok(json_response) = serde_json::from_str::<Value>(&text) {
-            if let Some(error) = json_response.get("error") {
-                let error_msg = error.as_str().unwrap_or("Unknown error");
-                if error_msg.contains("model") {
-                    return Err(DiracError::AIProcessingError(
-                        format!("Model '{}' not found. To install the model:\n", self.model) +
-                        "1. Ensure Ollama is running\n" +
-                        format!("2. Run 'ollama pull {}' to download the model", self.model).as_str()
-                    ));
-                }
-                return Err(DiracError::AIProcessingError(format!("Ollama error: {}", error_msg)));
-            }
+        let json_response = serde_json::from_str::<Value>(&text)
+            .map_err(|e| DiracError::AIProcessingError(format!("Invalid response from AI service: {}", e)))?;
+        if let Some(error) = json_response.get("error") {
+            return Err(api_error(error, &model));
+        }
 
-            // Extract the response from the JSON
-            if let Some(response) = json_response.get("response") {
-                let response_text = response.as_str().unwrap_or("").trim();
-                if response_text.is_empty() {
-                    // Return a default command suggestion for empty responses
-                    return Ok("COMMAND: ls\nEXPLANATION: Lists files and directories in the current directory. This is a safe default command when the request is unclear.".to_string());
-                }
+        // Extract the assistant message from the JSON
+        let content = json_response
+            .pointer("/message/content")
+            .and_then(|c| c.as_str())
+            .unwrap_or("");
+        Suggestion::parse(content)
+    }
 
-                // Parse the response format
-                let mut command = String::new();
-                let mut explanation = String::new();
+    async fn process_stream<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<AIResponse> {
+        let prompt = self.templates.render(kind, input, context, history)?;
+        let messages = chat_messages(prompt, history);
+        let model = self.current_model();
 
-                for line in response_text.lines() {
-                    if line.starts_with("COMMAND:") {
-                        command = line.trim_start_matches("COMMAND:").trim().to_string();
-                    } else if line.starts_with("EXPLANATION:") {
-                        explanation = line.trim_start_matches("EXPLANATION:").trim().to_string();
-                    }
-                }
+        let body = json!({
+            "model": model,
+            "messages": messages,
+            "format": Suggestion::schema(kind),
+            "stream": true
+        });
+        let response = self.send(&body, &model).await?;
 
-                // Ensure we always return a valid command and explanation
-                if command.is_empty() {
-                    command = "ls".to_string();
-                    explanation = if explanation.is_empty() {
-                        "Lists files and directories in the current directory. This is a safe default command when the request is unclear.".to_string()
-                    } else {
-                        explanation
-                    };
-                } else if explanation.is_empty() {
-                    explanation = "Executes the specified command.".to_string();
-                }
+        // Errors such as a missing model arrive as a single JSON body before any tokens
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.json::<Value>().await.unwrap_or_default();
+            return Err(match body.get("error") {
+                Some(error) => api_error(error, &model),
+                None => DiracError::AIProcessingError(format!("Ollama error: {}", status)),
+            });
+        }
 
-                // Return both command and explanation in a format that can be parsed by the terminal
-                return Ok(format!("COMMAND: {}\nEXPLANATION: {}", command, explanation));
-            }
+        // Ollama answers with one JSON object per line, each carrying the next tokens
+        let tokens = response_lines(response, self.policy.read_timeout).filter_map(move |line| {
+            let result = match line {
+                Ok(line) if line.is_empty() => None,
+                Ok(line) => match serde_json::from_str::<Value>(&line) {
+                    Ok(chunk) => match chunk.get("error") {
+                        Some(error) => Some(Err(api_error(error, &model))),
+                        None => chunk
+                            .pointer("/message/content")
+                            .and_then(|r| r.as_str())
+                            .filter(|r| !r.is_empty())
+                            .map(|r| Ok(r.to_string())),
+                    },
+                    Err(e) => Some(Err(DiracError::AIProcessingError(format!("Invalid response from AI service: {}", e)))),
+                },
+                Err(e) => Some(Err(e)),
+            };
+            future::ready(result)
+        });
+
+        Ok(AIResponse {
+            provider: self.name(),
+            cached: false,
+            stream: Box::pin(tokens),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_error_only_takes_missing_models_for_model_not_found() {
+        for message in [r#"model "llama3" not found, try pulling it first"#, "model 'llama3' not found", "model not found"] {
+            assert!(matches!(api_error(&json!(message), "llama3"), DiracError::ModelNotFound(model) if model == "llama3"), "{}", message);
         }
+        for message in ["model requires more system memory (8 GiB) than is available", "pull model manifest: file does not exist", "llama runner process has terminated"] {
+            assert!(matches!(api_error(&json!(message), "llama3"), DiracError::AIProcessingError(_)), "{}", message);
+        }
+    }
+
+    const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn lines(chunks: &[&'static str]) -> Vec<DiracResult<String>> {
+        let body = stream::iter(chunks.iter().map(|chunk| Ok::<_, String>(chunk.as_bytes())).collect::<Vec<_>>());
+        body_lines(body, READ_TIMEOUT).collect().await
+    }
+
+    fn texts(lines: Vec<DiracResult<String>>) -> Vec<String> {
+        lines.into_iter().map(|line| line.unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn body_lines_joins_lines_split_across_chunks() {
+        let lines = lines(&["{\"message\":", "{\"content\":\"ls\"}}\n{\"done\"", ":true}\n"]).await;
+        assert_eq!(texts(lines), [r#"{"message":{"content":"ls"}}"#, r#"{"done":true}"#]);
+    }
+
+    #[tokio::test]
+    async fn body_lines_trims_server_sent_events() {
+        let lines = lines(&["data: {\"a\":1}\r\n\r\ndata: [DONE]\r\n"]).await;
+        assert_eq!(texts(lines), ["data: {\"a\":1}", "", "data: [DONE]"]);
+    }
+
+    #[tokio::test]
+    async fn body_lines_keeps_a_last_line_without_newline() {
+        assert_eq!(texts(lines(&["first\nlast"]).await), ["first", "last"]);
+        assert!(lines(&[]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn body_lines_ends_with_a_read_error() {
+        let body = stream::iter(vec![Ok("partial".as_bytes()), Err("connection reset")]);
+        let lines: Vec<DiracResult<String>> = body_lines(body, READ_TIMEOUT).collect().await;
+        assert_eq!(lines.len(), 1);
+        assert!(matches!(&lines[0], Err(DiracError::AIProcessingError(e)) if e.contains("connection reset")));
+    }
 
-        // Return a default command for any parsing failures
-        Ok("COMMAND: ls\nEXPLANATION: Lists files and directories in the current directory. This is a safe default command when the request cannot be processed.".to_string())
+    #[tokio::test]
+    async fn body_lines_fails_when_the_body_stalls() {
+        let body = stream::iter(vec![Ok::<_, String>("line\n".as_bytes())]).chain(stream::pending());
+        let lines: Vec<DiracResult<String>> = body_lines(body, Duration::from_millis(50)).collect().await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].as_deref().unwrap(), "line");
+        assert!(matches!(&lines[1], Err(DiracError::AIProcessingError(e)) if e.contains("stopped responding")));
     }
 }
@@ -0,0 +1,260 @@
+use crate::core::lib::{AIProcessor, AIResponse, AIStream, ChatMessage, DiracError, DiracResult, PromptKind};
+use crate::core::suggestion::Suggestion;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a cached answer stays valid unless configured otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    suggestion: Suggestion,
+    /// Seconds since the Unix epoch
+    created: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// On-disk store of suggestions, kept as a single JSON file.
+#[derive(Debug)]
+struct ResponseCache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl ResponseCache {
+    fn load(path: PathBuf, ttl: Duration) -> Self {
+        // A missing or unreadable cache is simply an empty one
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        let cache = Self {
+            path,
+            ttl,
+            entries: Mutex::new(entries),
+        };
+        cache.prune();
+        cache
+    }
+
+    fn prune(&self) {
+        let oldest = now().saturating_sub(self.ttl.as_secs());
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.created >= oldest);
+    }
+
+    fn get(&self, key: &str) -> Option<Suggestion> {
+        let oldest = now().saturating_sub(self.ttl.as_secs());
+        self.entries
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|entry| entry.created >= oldest)
+            .map(|entry| entry.suggestion.clone())
+    }
+
+    fn insert(&self, key: String, suggestion: Suggestion) {
+        self.prune();
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key, CacheEntry { suggestion, created: now() });
+
+        // Failing to persist only costs a model round-trip next time
+        if let Some(parent) = self.path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(text) = serde_json::to_string(&*entries) {
+            let _ = std::fs::write(&self.path, text);
+        }
+    }
+}
+
+/// Stable across builds, unlike `DefaultHasher`, as keys are kept on disk.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+}
+
+/// Answers repeated requests from an on-disk cache before asking the
+/// wrapped backend. Entries are keyed by the normalized request, the prompt
+/// kind, the model, the working directory, the OS and a digest of the
+/// earlier turns, as a follow-up such as "now sort them" means something
+/// else after every request. The volatile extra
+/// context, such as recent commands, is deliberately left out of the key,
+/// and failure diagnoses are never cached since they hinge on it.
+pub struct CachedProcessor {
+    inner: Box<dyn AIProcessor>,
+    cache: Arc<ResponseCache>,
+}
+
+impl CachedProcessor {
+    pub fn new(inner: Box<dyn AIProcessor>, path: PathBuf, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(ResponseCache::load(path, ttl)),
+        }
+    }
+
+    /// Caches in `$XDG_CACHE_HOME/dirac/responses.json` or its platform equivalent.
+    pub fn with_default_path(inner: Box<dyn AIProcessor>, ttl: Duration) -> Self {
+        let path = dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("dirac")
+            .join("responses.json");
+        Self::new(inner, path, ttl)
+    }
+
+    fn key(&self, kind: PromptKind, input: &str, history: &[ChatMessage]) -> Option<String> {
+        // Both depend on command output, which is not part of the key
+        if matches!(kind, PromptKind::Diagnose | PromptKind::Ask) {
+            return None;
+        }
+        let request = input
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        let request = request.trim_end_matches(['?', '!', '.']);
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        let turns = match history {
+            [] => String::new(),
+            history => format!("{:016x}", fnv1a(serde_json::to_string(history).ok()?.as_bytes())),
+        };
+        Some(format!(
+            "{}\u{1f}{}\u{1f}{}\u{1f}{}\u{1f}{}\u{1f}{}",
+            self.inner.name(),
+            kind.name(),
+            std::env::consts::OS,
+            current_dir.display(),
+            turns,
+            request
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl AIProcessor for CachedProcessor {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn model(&self) -> Option<String> {
+        self.inner.model()
+    }
+
+    fn set_model(&self, model: &str) -> DiracResult<()> {
+        self.inner.set_model(model)
+    }
+
+    async fn is_model_loaded(&self) -> Option<bool> {
+        self.inner.is_model_loaded().await
+    }
+
+    async fn process<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<Suggestion> {
+        let key = self.key(kind, input, history);
+        if let Some(suggestion) = key.as_deref().and_then(|key| self.cache.get(key)) {
+            return Ok(suggestion);
+        }
+        let suggestion = self.inner.process(kind, input, context, history).await?;
+        // Answers asking for probes only make sense together with their results
+        if let (Some(key), true) = (key, suggestion.probes.is_empty()) {
+            self.cache.insert(key, suggestion.clone());
+        }
+        Ok(suggestion)
+    }
+
+    async fn process_stream<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<AIResponse> {
+        let key = self.key(kind, input, history);
+        if let Some(suggestion) = key.as_deref().and_then(|key| self.cache.get(key)) {
+            let response = serde_json::to_string(&suggestion)
+                .map_err(|e| DiracError::AIProcessingError(format!("Failed to encode suggestion: {}", e)))?;
+            return Ok(AIResponse {
+                provider: self.name(),
+                cached: true,
+                stream: Box::pin(stream::once(async move { Ok(response) })),
+            });
+        }
+
+        let response = self.inner.process_stream(kind, input, context, history).await?;
+
+        // Pass the tokens through and store the answer once it is complete and valid
+        let cache = Arc::clone(&self.cache);
+        let state = (response.stream, String::new(), key);
+        let stream: AIStream = Box::pin(stream::unfold(state, move |(mut inner, mut raw, mut key)| {
+            let cache = Arc::clone(&cache);
+            async move {
+                match inner.next().await {
+                    Some(Ok(chunk)) => {
+                        raw.push_str(&chunk);
+                        Some((Ok(chunk), (inner, raw, key)))
+                    }
+                    Some(Err(e)) => Some((Err(e), (inner, raw, None))),
+                    None => {
+                        if let (Some(key), Ok(suggestion)) = (key.take(), Suggestion::parse(&raw)) {
+                            if suggestion.probes.is_empty() {
+                                cache.insert(key, suggestion);
+                            }
+                        }
+                        None
+                    }
+                }
+            }
+        }));
+
+        Ok(AIResponse {
+            provider: response.provider,
+            cached: false,
+            stream,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::lib::ChatRole;
+    use crate::services::mock::ScriptedAIProcessor;
+
+    fn cached(ai: &ScriptedAIProcessor, name: &str) -> (CachedProcessor, PathBuf) {
+        let path = std::env::temp_dir().join(format!("dirac-cache-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (CachedProcessor::new(Box::new(ai.clone()), path.clone(), DEFAULT_TTL), path)
+    }
+
+    #[tokio::test]
+    async fn repeated_requests_are_answered_from_the_cache() {
+        let ai = ScriptedAIProcessor::new().suggest("", "ls", "");
+        let (processor, path) = cached(&ai, "repeat");
+        processor.process(PromptKind::Generate, "List  files", "", &[]).await.unwrap();
+        processor.process(PromptKind::Generate, "list files?", "", &[]).await.unwrap();
+        assert_eq!(ai.requests().len(), 1);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn follow_ups_are_keyed_by_the_earlier_turns() {
+        let ai = ScriptedAIProcessor::new().suggest("", "sort", "");
+        let (processor, path) = cached(&ai, "history");
+        let after_logs = [ChatMessage::new(ChatRole::User, "show the logs"), ChatMessage::new(ChatRole::Assistant, "ls /var/log")];
+        let after_users = [ChatMessage::new(ChatRole::User, "list users"), ChatMessage::new(ChatRole::Assistant, "cut -d: -f1 /etc/passwd")];
+
+        processor.process(PromptKind::Generate, "now sort them", "", &after_logs).await.unwrap();
+        processor.process(PromptKind::Generate, "now sort them", "", &after_users).await.unwrap();
+        processor.process(PromptKind::Generate, "now sort them", "", &[]).await.unwrap();
+        assert_eq!(ai.requests().len(), 3);
+        processor.process(PromptKind::Generate, "now sort them", "", &after_logs).await.unwrap();
+        assert_eq!(ai.requests().len(), 3);
+        let _ = std::fs::remove_file(path);
+    }
+}
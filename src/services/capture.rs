@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+/// Bytes kept from each end of a command's output.
+pub const MAX_CAPTURED_BYTES: usize = 32 * 1024;
+
+/// A copy of a command's output as it streams past, keeping only its start
+/// and its end, so a build printing megabytes costs no more memory than one
+/// printing a page. The start usually says what ran, the end what failed.
+#[derive(Debug, Default)]
+pub struct Capture {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    omitted: usize,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        let room = MAX_CAPTURED_BYTES - self.head.len();
+        let into_head = room.min(bytes.len());
+        self.head.extend_from_slice(&bytes[..into_head]);
+        bytes = &bytes[into_head..];
+
+        self.tail.extend(bytes);
+        let excess = self.tail.len().saturating_sub(MAX_CAPTURED_BYTES);
+        self.tail.drain(..excess);
+        self.omitted += excess;
+    }
+
+    pub fn last_byte(&self) -> Option<u8> {
+        self.tail.back().or(self.head.last()).copied()
+    }
+
+    /// The captured output as text, with `convert` turning bytes into it,
+    /// and a note where the middle was left out.
+    pub fn text(&self, convert: impl Fn(&[u8]) -> String) -> String {
+        let (front, back) = self.tail.as_slices();
+        let tail = [front, back].concat();
+        if self.omitted == 0 {
+            return convert(&[self.head.as_slice(), &tail].concat());
+        }
+        format!("{}\n[... {} bytes omitted ...]\n{}", convert(&self.head), self.omitted, convert(&tail))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lossy(bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).to_string()
+    }
+
+    #[test]
+    fn short_output_is_kept_whole() {
+        let mut capture = Capture::new();
+        assert_eq!(capture.last_byte(), None);
+        capture.push(b"hello ");
+        capture.push(b"world\n");
+        assert_eq!(capture.text(lossy), "hello world\n");
+        assert_eq!(capture.last_byte(), Some(b'\n'));
+    }
+
+    #[test]
+    fn output_filling_both_ends_is_kept_whole() {
+        let mut capture = Capture::new();
+        capture.push(&vec![b'a'; MAX_CAPTURED_BYTES]);
+        capture.push(&vec![b'b'; MAX_CAPTURED_BYTES]);
+        let text = capture.text(lossy);
+        assert_eq!(text.len(), 2 * MAX_CAPTURED_BYTES);
+        assert!(!text.contains("omitted"));
+    }
+
+    #[test]
+    fn long_output_keeps_its_start_and_end() {
+        let mut capture = Capture::new();
+        capture.push(b"start");
+        for _ in 0..100 {
+            capture.push(&[b'x'; 1024]);
+        }
+        capture.push(b"end");
+        assert_eq!(capture.last_byte(), Some(b'd'));
+
+        let text = capture.text(lossy);
+        let omitted = 5 + 100 * 1024 + 3 - 2 * MAX_CAPTURED_BYTES;
+        let (head, rest) = text.split_once('\n').unwrap();
+        let (note, tail) = rest.split_once('\n').unwrap();
+        assert!(head.starts_with("start") && head.len() == MAX_CAPTURED_BYTES);
+        assert_eq!(note, format!("[... {} bytes omitted ...]", omitted));
+        assert!(tail.ends_with("end") && tail.len() == MAX_CAPTURED_BYTES);
+    }
+}
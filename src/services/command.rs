@@ -1,16 +1,170 @@
 use crate::core::lib::{CommandExecutor, DiracError, DiracResult};
-use std::process::Command;
+use crate::services::capture::Capture;
+use crate::services::pty::{self, PtyProcess, PtyRun, PtyStatus};
+use crate::services::session::{self, ShellSession, ShellState};
 use std::env;
-use which::which;
-use std::cell::RefCell;
+use std::ffi::OsString;
+use std::cell::{Cell, RefCell};
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::process::Command as TokioCommand;
 use tokio::time::timeout;
 
+/// How long a command may run without a terminal before it is killed.
+/// With no terminal there is no CTRL-C to stop a command that hangs, e.g.
+/// waiting for input that never comes, so it is bounded instead.
+const PIPED_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The process group of the command running in the foreground, for the
+/// signal handler to pass CTRL-C and CTRL-\ on to. Commands run in a group
+/// apart from Dirac's, so everything they started is reached and Dirac is
+/// not.
+#[derive(Debug, Default)]
+pub struct Foreground {
+    /// 0 while no command runs
+    pgid: AtomicI32,
+    /// The pseudo-terminal of the command, if it has one, -1 otherwise.
+    /// Signals go to whichever group runs in its foreground, and CTRL-Z
+    /// only works with one.
+    tty: AtomicI32,
+    /// Interrupts since the command last had a chance to handle one
+    interrupts: AtomicUsize,
+    interrupted: AtomicBool,
+}
+
+impl Foreground {
+    pub(crate) fn start(&self, pgid: i32, tty: Option<RawFd>) {
+        self.interrupts.store(0, Ordering::SeqCst);
+        self.interrupted.store(false, Ordering::SeqCst);
+        self.tty.store(tty.unwrap_or(-1), Ordering::SeqCst);
+        self.pgid.store(pgid, Ordering::SeqCst);
+    }
+
+    /// Forgets the command, returning whether it was interrupted.
+    pub(crate) fn finish(&self) -> bool {
+        self.pgid.store(0, Ordering::SeqCst);
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// Counts an interrupt of the running command and picks the signal
+    /// delivering it: `signal` itself, or SIGKILL for a repeated CTRL-C.
+    pub(crate) fn escalate(&self, signal: i32) -> i32 {
+        self.interrupted.store(true, Ordering::SeqCst);
+        let repeated = self.interrupts.fetch_add(1, Ordering::SeqCst) > 0;
+        if signal == libc::SIGINT && repeated {
+            libc::SIGKILL
+        } else {
+            signal
+        }
+    }
+
+    /// Notes an interrupt the command handles itself, such as CTRL-C in a
+    /// REPL, after which another one is not a reason to kill it.
+    pub(crate) fn handled(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.interrupts.store(0, Ordering::SeqCst);
+    }
+
+    /// Sends `signal` to the whole process group of the running command.
+    pub(crate) fn kill(&self, signal: i32) {
+        let mut pgid = self.pgid.load(Ordering::SeqCst);
+        let tty = self.tty.load(Ordering::SeqCst);
+        if pgid > 0 && tty >= 0 {
+            pgid = unsafe { libc::tcgetpgrp(tty) };
+        }
+        if pgid > 0 {
+            unsafe { libc::kill(-pgid, signal) };
+        }
+    }
+
+    /// Passes `signal` on to the running command, escalating a second
+    /// CTRL-C to SIGKILL. Returns whether a command was running and took
+    /// the signal.
+    pub fn forward(&self, signal: i32) -> bool {
+        if self.pgid.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+        if signal == libc::SIGTSTP {
+            if self.tty.load(Ordering::SeqCst) < 0 {
+                return false;
+            }
+            self.kill(signal);
+            return true;
+        }
+        self.kill(self.escalate(signal));
+        true
+    }
+}
+
+/// What a job is doing, as `jobs` shows it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(PtyStatus),
+}
+
+impl JobState {
+    fn describe(&self) -> String {
+        match self {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done(PtyStatus::Exited { exit_code: Some(0), .. }) => "Done".to_string(),
+            JobState::Done(PtyStatus::Exited { exit_code: Some(code), .. }) => format!("Exit {}", code),
+            JobState::Done(PtyStatus::Exited { signal: Some(signal), .. }) => signal.clone(),
+            JobState::Done(_) => "Done".to_string(),
+        }
+    }
+}
+
+/// A command running in the background or stopped, numbered as in `%1`.
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub state: JobState,
+    process: PtyProcess,
+}
+
+/// The shell commands run in, kept between them.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum Session {
+    /// In a pseudo-terminal, when Dirac runs in one
+    Terminal(PtyProcess),
+    Piped(ShellSession),
+}
+
+impl Session {
+    fn has_exited(&mut self) -> bool {
+        match self {
+            Session::Terminal(process) => process.try_wait().is_some(),
+            Session::Piped(session) => session.has_exited(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ShellCommandExecutor {
     current_dir: RefCell<String>,
     shell_path: String,
+    /// Commands get a pseudo-terminal of their own when Dirac runs in one
+    interactive: bool,
+    last_exit_code: Cell<Option<i32>>,
+    last_stdout: RefCell<String>,
+    last_stderr: RefCell<String>,
+    last_output_shown: Cell<bool>,
+    foreground: Arc<Foreground>,
+    /// Background and stopped commands, the current job last
+    jobs: RefCell<Vec<Job>>,
+    /// Started on first use, and again in `current_dir` and `env` after it
+    /// exits
+    session: RefCell<Option<Session>>,
+    /// Exported variables of the session shell as of its last command
+    env: RefCell<Vec<(OsString, OsString)>>,
+    /// Why the session shell was lost since last asked, if it was
+    session_lost: RefCell<Option<String>>,
 }
 
 impl ShellCommandExecutor {
@@ -23,58 +177,326 @@ impl ShellCommandExecutor {
                     .unwrap_or_else(|_| String::from("/"))
             ),
             shell_path,
+            interactive: pty::is_interactive(),
+            last_exit_code: Cell::new(None),
+            last_stdout: RefCell::new(String::new()),
+            last_stderr: RefCell::new(String::new()),
+            last_output_shown: Cell::new(false),
+            foreground: Arc::default(),
+            jobs: RefCell::new(Vec::new()),
+            session: RefCell::new(None),
+            env: RefCell::new(env::vars_os().collect()),
+            session_lost: RefCell::new(None),
         }
     }
 
-    pub fn is_valid_command(&self, command: &str) -> bool {
+    /// Whether `command` starts with a program on the session's `PATH`, an
+    /// alias or function of the session shell, or a builtin or function
+    /// definition changing it.
+    pub async fn is_valid_command(&self, command: &str) -> bool {
         let first_word = command.split_whitespace().next().unwrap_or("");
         if first_word.is_empty() {
             return false;
         }
-        if first_word == "cd" {
+        if matches!(first_word, "cd" | "jobs" | "fg" | "bg" | "export" | "unset" | "source" | "." | "alias" | "unalias") {
+            return true;
+        }
+        // Defining a function
+        if first_word.len() > 2 && first_word.ends_with("()") {
+            return true;
+        }
+        let path = self.env.borrow().iter().find(|(name, _)| name == "PATH").map(|(_, value)| value.clone());
+        if which::which_in(first_word, path, self.get_current_dir()).is_ok() {
             return true;
         }
-        which(first_word).is_ok()
+        self.defines(first_word).await
+    }
+
+    /// Whether `name` is an alias or function of the session shell.
+    async fn defines(&self, name: &str) -> bool {
+        let Ok(mut session) = self.take_session().await else {
+            return false;
+        };
+        let check = session::definition_check(name);
+        let exit_code = match &mut session {
+            Session::Terminal(process) => process.check(&check).await.ok().flatten(),
+            Session::Piped(session) => session.check(&check).await.ok().flatten(),
+        };
+        if exit_code.is_some() {
+            *self.session.borrow_mut() = Some(session);
+        } else {
+            self.lose_session("The session shell stopped responding");
+        }
+        exit_code == Some(0)
+    }
+
+    /// The session shell, a new one if there is none or it has exited,
+    /// started where the last one was left.
+    async fn take_session(&self) -> DiracResult<Session> {
+        let session = self.session.borrow_mut().take();
+        if let Some(mut session) = session {
+            if !session.has_exited() {
+                return Ok(session);
+            }
+            self.lose_session("The session shell exited");
+        }
+        let cwd = self.get_current_dir();
+        let env = self.env.borrow().clone();
+        let session = if self.interactive {
+            PtyProcess::spawn_session(&self.shell_path, &cwd, &env).map(Session::Terminal)
+        } else {
+            ShellSession::spawn(&self.shell_path, &cwd, &env)
+                .await
+                .map(Session::Piped)
+                .map_err(|e| e.to_string())
+        };
+        session.map_err(|e| DiracError::CommandExecutionError(format!("Failed to start {}: {}", self.shell_path, e)))
+    }
+
+    /// Takes over the directory and exported variables the session shell
+    /// was left with, the directory as Dirac's own too.
+    fn sync(&self, state: ShellState) {
+        let _ = env::set_current_dir(&state.cwd);
+        *self.current_dir.borrow_mut() = state.cwd;
+        *self.env.borrow_mut() = state.env;
+    }
+
+    /// Remembers that the session shell is gone, for `session_notice`.
+    fn lose_session(&self, reason: &str) {
+        self.session_lost.borrow_mut().get_or_insert_with(|| reason.to_string());
+    }
+
+    /// Tells the user the session shell was lost since last asked, and what
+    /// of it the next one keeps.
+    pub fn session_notice(&self) -> Option<String> {
+        let reason = self.session_lost.borrow_mut().take()?;
+        Some(format!(
+            "{}. The next command starts a new one, with the same directory and exported variables but without the aliases, functions and other settings of the old one.",
+            reason
+        ))
     }
 
     pub fn get_current_dir(&self) -> String {
         self.current_dir.borrow().to_string()
     }
 
-    fn handle_cd(&self, args: &str) -> DiracResult<String> {
-        let path = args.trim();
-        if path.is_empty() {
-            return Err(DiracError::CommandExecutionError("No path specified for cd".to_string()));
+    /// Exported variables of the session shell as of its last command.
+    pub fn env(&self) -> Vec<(OsString, OsString)> {
+        self.env.borrow().clone()
+    }
+
+    /// Exit code of the last executed command, `None` if it timed out or
+    /// could not be started.
+    pub fn last_exit_code(&self) -> Option<i32> {
+        self.last_exit_code.get()
+    }
+
+    /// Standard output of the last executed command, whether it succeeded
+    /// or not.
+    pub fn last_stdout(&self) -> String {
+        self.last_stdout.borrow().clone()
+    }
+
+    /// Error output of the last executed command, empty if it printed none.
+    pub fn last_stderr(&self) -> String {
+        self.last_stderr.borrow().clone()
+    }
+
+    /// Whether the output of the last command went straight to the screen
+    /// as it ran, so it need not be shown again.
+    pub fn output_shown(&self) -> bool {
+        self.last_output_shown.get()
+    }
+
+    /// The running command, for the signal handler.
+    pub fn foreground(&self) -> Arc<Foreground> {
+        Arc::clone(&self.foreground)
+    }
+
+    /// Runs `command` in a pseudo-terminal, without a timeout, as it may
+    /// wait for the user. Its output, stderr included, counts as stdout. A
+    /// trailing `&` starts it as a background job instead, in a shell of its
+    /// own that has the session's directory and variables but not its
+    /// aliases and functions.
+    async fn execute_in_terminal(&self, command: &str) -> DiracResult<String> {
+        let background = command
+            .trim_end()
+            .strip_suffix('&')
+            .filter(|rest| !rest.ends_with('&') && !rest.ends_with('\\'))
+            .map(str::trim_end);
+        let failed = |e: String| DiracError::CommandExecutionError(format!("Failed to execute command: {}", e));
+        if let Some(command) = background {
+            let process = PtyProcess::spawn(&self.shell_path, command, &self.get_current_dir(), &self.env.borrow()).map_err(failed)?;
+            let id = self.next_job_id();
+            let started = format!("[{}] {}", id, process.pid());
+            self.jobs.borrow_mut().push(Job { id, command: command.to_string(), state: JobState::Running, process });
+            self.last_exit_code.set(Some(0));
+            return Ok(started);
         }
 
-        let path = if path == "~" || path.starts_with("~/") {
-            path.replacen("~", &env::var("HOME").unwrap_or_else(|_| String::from("/")), 1)
-        } else {
-            path.to_string()
+        let Session::Terminal(mut process) = self.take_session().await? else {
+            return Err(failed("the session shell has no terminal".to_string()));
         };
+        let run = process.run(command, &self.foreground).await.map_err(failed)?;
+        self.finish_in_terminal(None, command.to_string(), process, run)
+    }
+
+    /// Handles what became of `process` once it left the terminal. A
+    /// stopped one becomes job `id`, or a new job, taking its shell along
+    /// if it was the session's.
+    fn finish_in_terminal(&self, id: Option<usize>, command: String, mut process: PtyProcess, run: PtyRun) -> DiracResult<String> {
+        *self.last_stdout.borrow_mut() = run.output.clone();
 
-        let new_dir = if path.starts_with('/') {
-            path
+        let (exit_code, signal) = match run.status {
+            PtyStatus::Stopped => {
+                if process.end_session() {
+                    self.lose_session("The session shell went along with the stopped job");
+                }
+                let id = id.unwrap_or_else(|| self.next_job_id());
+                let mut jobs = self.jobs.borrow_mut();
+                jobs.push(Job { id, command, state: JobState::Stopped, process });
+                // Only the notice is new; the output is already on screen
+                return Ok(job_line(&jobs, jobs.len() - 1));
+            }
+            PtyStatus::Exited { exit_code, signal } => (exit_code, signal),
+        };
+        match run.state {
+            Some(state) => {
+                self.sync(state);
+                *self.session.borrow_mut() = Some(Session::Terminal(process));
+            }
+            None if process.is_session() => self.lose_session(if run.interrupted {
+                "The session shell was killed along with the command"
+            } else {
+                "The session shell exited"
+            }),
+            None => {}
+        }
+        self.last_output_shown.set(true);
+        self.last_exit_code.set(exit_code);
+        match (exit_code, signal) {
+            (Some(0), _) => Ok(run.output),
+            _ if run.interrupted => Err(DiracError::Interrupted),
+            (Some(code), _) => Err(DiracError::CommandExecutionError(format!("Command failed (exit code: {})", code))),
+            (None, signal) => Err(DiracError::CommandExecutionError(format!(
+                "Command terminated by {}",
+                signal.unwrap_or_else(|| "a signal".to_string())
+            ))),
+        }
+    }
+
+    /// Number for a new job: one more than the highest in use, as in bash.
+    fn next_job_id(&self) -> usize {
+        self.jobs.borrow().iter().map(|job| job.id).max().unwrap_or(0) + 1
+    }
+
+    /// Position in the job table of `%n`, `n` or, if `spec` is empty, the
+    /// current job.
+    fn find_job(&self, builtin: &str, spec: &str) -> DiracResult<usize> {
+        if !self.interactive {
+            return Err(DiracError::CommandExecutionError(format!(
+                "{}: no job control, as Dirac is not running in a terminal",
+                builtin
+            )));
+        }
+        let jobs = self.jobs.borrow();
+        let spec = spec.trim();
+        let found = if spec.is_empty() || spec == "%" || spec == "%+" {
+            jobs.len().checked_sub(1)
         } else {
-            format!("{}/{}", self.current_dir.borrow(), path)
+            let id = spec.trim_start_matches('%').parse::<usize>().ok();
+            jobs.iter().position(|job| Some(job.id) == id)
         };
+        found.ok_or_else(|| {
+            let spec = if spec.is_empty() { "current" } else { spec };
+            DiracError::CommandExecutionError(format!("{}: {}: no such job", builtin, spec))
+        })
+    }
 
-        // Resolve the path to handle . and .. components and symbolic links
-        let _canonical_path = std::fs::canonicalize(&new_dir)
-            .map_err(|e| DiracError::CommandExecutionError(format!("Invalid path: {}", e)))?;
+    /// Notices for jobs that finished or stopped since last asked, which
+    /// forgets the finished ones.
+    pub fn job_notices(&self) -> Vec<String> {
+        let mut jobs = self.jobs.borrow_mut();
+        let mut notices = Vec::new();
+        for index in 0..jobs.len() {
+            let Some(status) = jobs[index].process.try_wait() else {
+                continue;
+            };
+            jobs[index].state = match status {
+                PtyStatus::Stopped => JobState::Stopped,
+                exited => JobState::Done(exited),
+            };
+            notices.push(job_line(&jobs, index));
+        }
+        jobs.retain(|job| !matches!(job.state, JobState::Done(_)));
+        notices
+    }
 
-        match env::set_current_dir(&new_dir) {
-            Ok(_) => {
-                *self.current_dir.borrow_mut() = new_dir;
-                Ok(String::new())
-            }
-            Err(e) => Err(DiracError::CommandExecutionError(format!("Failed to change directory: {}", e)))
+    /// Handles the `jobs` builtin.
+    fn list_jobs(&self) -> DiracResult<String> {
+        let mut lines = self.job_notices();
+        let jobs = self.jobs.borrow();
+        lines.extend((0..jobs.len()).map(|index| job_line(&jobs, index)));
+        Ok(lines.join("\n"))
+    }
+
+    /// Handles the `fg` builtin for the job at `index`, which then exits
+    /// with its own exit code.
+    async fn foreground_job(&self, index: usize) -> DiracResult<String> {
+        let mut job = self.jobs.borrow_mut().remove(index);
+        println!("{}", job.command);
+        let run = job
+            .process
+            .attach(&self.foreground, true)
+            .await
+            .map_err(|e| DiracError::CommandExecutionError(format!("Failed to execute command: {}", e)))?;
+        self.finish_in_terminal(Some(job.id), job.command, job.process, run)
+    }
+
+    /// Handles the `bg` builtin.
+    fn background_job(&self, spec: &str) -> DiracResult<String> {
+        let index = self.find_job("bg", spec)?;
+        let mut jobs = self.jobs.borrow_mut();
+        jobs[index].process.resume();
+        jobs[index].state = JobState::Running;
+        Ok(job_line(&jobs, index))
+    }
+
+    /// Records the exit code of a builtin the way the shell would.
+    fn finish_builtin(&self, result: DiracResult<String>) -> DiracResult<String> {
+        self.last_exit_code.set(Some(if result.is_ok() { 0 } else { 1 }));
+        if let Err(e) = &result {
+            *self.last_stderr.borrow_mut() = e.to_string();
         }
+        result
+    }
+}
+
+/// A line of `jobs` for the job at `index`, marked `+` if it is the current
+/// job and `-` if it is the previous one.
+fn job_line(jobs: &[Job], index: usize) -> String {
+    let job = &jobs[index];
+    let marker = match jobs.len() - index {
+        1 => '+',
+        2 => '-',
+        _ => ' ',
+    };
+    let background = if job.state == JobState::Running { " &" } else { "" };
+    format!("[{}]{}  {:<24}{}{}", job.id, marker, job.state.describe(), job.command, background)
+}
+
+impl Default for ShellCommandExecutor {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl CommandExecutor for ShellCommandExecutor {
     async fn execute(&self, command: &str) -> DiracResult<String> {
+        self.last_exit_code.set(None);
+        self.last_stdout.borrow_mut().clear();
+        self.last_stderr.borrow_mut().clear();
+        self.last_output_shown.set(false);
         if command.trim().is_empty() {
             return Err(DiracError::CommandExecutionError("Empty command provided".to_string()));
         }
@@ -83,53 +505,83 @@ impl CommandExecutor for ShellCommandExecutor {
         let cmd = parts[0];
         let args = parts.get(1).unwrap_or(&"");
 
-        if cmd == "cd" {
-            return self.handle_cd(args);
+        match cmd {
+            "jobs" => return self.finish_builtin(self.list_jobs()),
+            "bg" => return self.finish_builtin(self.background_job(args)),
+            "fg" => {
+                return match self.find_job("fg", args) {
+                    Ok(index) => self.foreground_job(index).await,
+                    Err(e) => self.finish_builtin(Err(e)),
+                };
+            }
+            _ => {}
         }
 
-        // Update current directory from environment in case it was changed externally
-        if let Ok(current_dir) = env::current_dir() {
-            *self.current_dir.borrow_mut() = current_dir.to_string_lossy().to_string();
+        if self.interactive {
+            return self.execute_in_terminal(command).await;
         }
+        let Session::Piped(mut session) = self.take_session().await? else {
+            return Err(DiracError::CommandExecutionError(
+                "Failed to execute command: the session shell has a terminal".to_string(),
+            ));
+        };
 
-        // Set a reasonable timeout for commands
-        let timeout_duration = Duration::from_secs(30);
-
-        // Use tokio's async Command for all commands to ensure proper output handling
-        let output = TokioCommand::new(&self.shell_path)
-            .arg("-c")
-            .arg(command)
-            .env("TERM", "xterm-256color")
-            .env("SHELL", &self.shell_path)
-            .env("PATH", env::var("PATH").unwrap_or_else(|_| String::from("/usr/local/bin:/usr/bin:/bin")))
-            .current_dir(&*self.current_dir.borrow())
-            .output()
-            .await
-            .map_err(|e| DiracError::CommandExecutionError(format!("Failed to execute command: {}", e)))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-
-        // Update current directory after command execution
-        if let Ok(new_dir) = env::current_dir() {
-            *self.current_dir.borrow_mut() = new_dir.to_string_lossy().to_string();
+        self.last_output_shown.set(true);
+        self.foreground.start(session.pid(), None);
+        let mut stdout = Capture::new();
+        let mut stderr = Capture::new();
+        let finished = timeout(PIPED_COMMAND_TIMEOUT, session.run(command, &mut stdout, &mut stderr)).await;
+        if finished.is_err() {
+            // Taking the shell along, as it would wait for the command
+            self.foreground.kill(libc::SIGKILL);
+            self.lose_session("The session shell was killed along with the command that timed out");
         }
+        let interrupted = self.foreground.finish();
+        // Kept even when the command timed out, to tell what it was doing
+        let stdout = stdout.text(|bytes| String::from_utf8_lossy(bytes).to_string());
+        let stderr = stderr.text(|bytes| String::from_utf8_lossy(bytes).to_string());
+        *self.last_stdout.borrow_mut() = stdout.clone();
+        *self.last_stderr.borrow_mut() = stderr.clone();
 
-        // Handle command execution status and output
-        if output.status.success() {
+        let state = finished
+            .map_err(|_| DiracError::CommandExecutionError(
+                format!("Command timed out after {} seconds", PIPED_COMMAND_TIMEOUT.as_secs())
+            ))?
+            .map_err(|e| {
+                self.lose_session("The session shell stopped responding");
+                DiracError::CommandExecutionError(format!("Failed to execute command: {}", e))
+            })?;
+        let exit_code = match state {
+            Some(state) => {
+                let exit_code = state.exit_code;
+                self.sync(state);
+                *self.session.borrow_mut() = Some(Session::Piped(session));
+                Some(exit_code)
+            }
+            // The command ended the shell, such as with `exit`
+            None => {
+                self.lose_session(if interrupted {
+                    "The session shell was killed along with the command"
+                } else {
+                    "The session shell exited"
+                });
+                session.exit_status().await.ok().and_then(|status| status.code())
+            }
+        };
+        self.last_exit_code.set(exit_code);
+
+        // Handle command execution status and output, which is already on screen
+        if exit_code == Some(0) {
             if !stdout.is_empty() {
                 Ok(stdout)
-            } else if !stderr.is_empty() {
-                Ok(stderr)
             } else {
-                Ok(String::new())
+                Ok(stderr)
             }
+        } else if interrupted {
+            Err(DiracError::Interrupted)
         } else {
             Err(DiracError::CommandExecutionError(
-                format!("Command failed (exit code: {}): {}", 
-                    output.status.code().unwrap_or(-1),
-                    if !stderr.is_empty() { stderr.trim() } else { stdout.trim() }
-                )
+                format!("Command failed (exit code: {})", exit_code.unwrap_or(-1))
             ))
         }
     }
@@ -0,0 +1,366 @@
+use crate::core::lib::{CommandOutput, ContextProvider, SessionState};
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How long `git` may take before its section is left out, as a large or
+/// networked repository can keep `git status` busy for a long time.
+const GIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs `git` in `cwd`, returning its output when it succeeds in time.
+async fn git(cwd: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(GIT_TIMEOUT, output).await.ok()?.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+/// Current branch and working tree status of the enclosing git repository.
+#[derive(Debug, Default)]
+pub struct GitContext;
+
+#[async_trait::async_trait]
+impl ContextProvider for GitContext {
+    fn name(&self) -> &str {
+        "git"
+    }
+
+    fn description(&self) -> &str {
+        "Branch and status of the current git repository"
+    }
+
+    fn default_budget(&self) -> usize {
+        600
+    }
+
+    async fn collect(&self, session: &SessionState<'_>) -> Option<String> {
+        // The first line reads e.g. `## main...origin/main [ahead 1]`
+        let status = git(session.cwd, &["status", "--short", "--branch"]).await?;
+        let mut lines = status.lines();
+        let branch = lines.next()?.trim_start_matches("## ");
+        let changes: Vec<&str> = lines.collect();
+        if changes.is_empty() {
+            Some(format!("branch: {}\nworking tree clean", branch))
+        } else {
+            Some(format!("branch: {}\nchanges:\n{}", branch, changes.join("\n")))
+        }
+    }
+}
+
+/// The last few commands of the session with their exit codes.
+#[derive(Debug)]
+pub struct RecentCommandsContext {
+    count: usize,
+}
+
+impl RecentCommandsContext {
+    pub fn new(count: usize) -> Self {
+        Self { count }
+    }
+}
+
+impl Default for RecentCommandsContext {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+#[async_trait::async_trait]
+impl ContextProvider for RecentCommandsContext {
+    fn name(&self) -> &str {
+        "commands"
+    }
+
+    fn description(&self) -> &str {
+        "Last commands run in this session and their exit codes"
+    }
+
+    fn default_budget(&self) -> usize {
+        500
+    }
+
+    async fn collect(&self, session: &SessionState<'_>) -> Option<String> {
+        let start = session.recent_commands.len().saturating_sub(self.count);
+        let lines: Vec<String> = session.recent_commands[start..]
+            .iter()
+            .map(|record| match record.exit_code {
+                Some(code) => format!("$ {}  (exit {})", record.command, code),
+                None => format!("$ {}  (did not finish)", record.command),
+            })
+            .collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+}
+
+/// The last command with what it printed to stdout and stderr.
+pub fn describe_output(output: &CommandOutput) -> String {
+    let status = match output.exit_code {
+        Some(code) => format!("exit {}", code),
+        None => "did not finish".to_string(),
+    };
+    let mut text = format!("$ {}  ({})", output.command, status);
+    for (name, stream) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        if !stream.trim().is_empty() {
+            text.push_str(&format!("\n{}:\n{}", name, stream.trim_end()));
+        }
+    }
+    if output.stdout.trim().is_empty() && output.stderr.trim().is_empty() {
+        text.push_str("\n(no output)");
+    }
+    text
+}
+
+/// What the last command printed, so requests can refer to it.
+#[derive(Debug, Default)]
+pub struct LastOutputContext;
+
+#[async_trait::async_trait]
+impl ContextProvider for LastOutputContext {
+    fn name(&self) -> &str {
+        "output"
+    }
+
+    fn description(&self) -> &str {
+        "Output of the last command, truncated and with secrets redacted"
+    }
+
+    fn default_budget(&self) -> usize {
+        1500
+    }
+
+    async fn collect(&self, session: &SessionState<'_>) -> Option<String> {
+        session.last_output.map(describe_output)
+    }
+}
+
+/// Marker files identifying a kind of project, with its name.
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "Rust (cargo)"),
+    ("package.json", "Node.js"),
+    ("pyproject.toml", "Python"),
+    ("requirements.txt", "Python"),
+    ("setup.py", "Python"),
+    ("go.mod", "Go"),
+    ("pom.xml", "Java (maven)"),
+    ("build.gradle", "Java (gradle)"),
+    ("build.gradle.kts", "Kotlin (gradle)"),
+    ("Gemfile", "Ruby (bundler)"),
+    ("composer.json", "PHP (composer)"),
+    ("mix.exs", "Elixir (mix)"),
+    ("CMakeLists.txt", "C/C++ (cmake)"),
+    ("Makefile", "make"),
+    ("Dockerfile", "Docker"),
+    ("docker-compose.yml", "Docker Compose"),
+    ("compose.yaml", "Docker Compose"),
+];
+
+/// Node package managers, recognized by their lock files.
+const NODE_LOCKFILES: &[(&str, &str)] = &[
+    ("pnpm-lock.yaml", "pnpm"),
+    ("yarn.lock", "yarn"),
+    ("bun.lockb", "bun"),
+    ("package-lock.json", "npm"),
+];
+
+/// Languages and build tools detected from marker files in the working directory.
+#[derive(Debug, Default)]
+pub struct ProjectTypeContext;
+
+#[async_trait::async_trait]
+impl ContextProvider for ProjectTypeContext {
+    fn name(&self) -> &str {
+        "project"
+    }
+
+    fn description(&self) -> &str {
+        "Project type detected from files such as Cargo.toml or package.json"
+    }
+
+    fn default_budget(&self) -> usize {
+        200
+    }
+
+    async fn collect(&self, session: &SessionState<'_>) -> Option<String> {
+        let mut kinds: Vec<String> = Vec::new();
+        for (marker, kind) in PROJECT_MARKERS {
+            if !session.cwd.join(marker).exists() {
+                continue;
+            }
+            let kind = if *marker == "package.json" {
+                match NODE_LOCKFILES.iter().find(|(lockfile, _)| session.cwd.join(lockfile).exists()) {
+                    Some((_, manager)) => format!("{} ({})", kind, manager),
+                    None => kind.to_string(),
+                }
+            } else {
+                kind.to_string()
+            };
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        (!kinds.is_empty()).then(|| kinds.join(", "))
+    }
+}
+
+/// The Python virtualenv or conda environment active in the session shell.
+#[derive(Debug, Default)]
+pub struct VirtualenvContext;
+
+#[async_trait::async_trait]
+impl ContextProvider for VirtualenvContext {
+    fn name(&self) -> &str {
+        "virtualenv"
+    }
+
+    fn description(&self) -> &str {
+        "Active Python virtualenv or conda environment"
+    }
+
+    fn default_budget(&self) -> usize {
+        200
+    }
+
+    async fn collect(&self, session: &SessionState<'_>) -> Option<String> {
+        let mut lines = Vec::new();
+        if let Some(path) = session.var("VIRTUAL_ENV") {
+            lines.push(format!("virtualenv: {}", path.to_string_lossy()));
+        }
+        if let Some(name) = session.var("CONDA_DEFAULT_ENV") {
+            lines.push(format!("conda environment: {}", name.to_string_lossy()));
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+}
+
+/// The shell commands are run with.
+#[derive(Debug, Default)]
+pub struct ShellContext;
+
+#[async_trait::async_trait]
+impl ContextProvider for ShellContext {
+    fn name(&self) -> &str {
+        "shell"
+    }
+
+    fn description(&self) -> &str {
+        "Shell used to run commands"
+    }
+
+    fn default_budget(&self) -> usize {
+        100
+    }
+
+    async fn collect(&self, _session: &SessionState<'_>) -> Option<String> {
+        // Mirrors the shell picked by ShellCommandExecutor
+        let path = std::env::var("SHELL").unwrap_or_else(|_| String::from("/bin/sh"));
+        let name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.clone());
+        Some(format!("{} ({})", name, path))
+    }
+}
+
+/// The OS distribution as named by `/etc/os-release`, plus the architecture.
+#[derive(Debug, Default)]
+pub struct DistroContext;
+
+impl DistroContext {
+    fn os_release() -> Option<String> {
+        let text = std::fs::read_to_string("/etc/os-release")
+            .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+            .ok()?;
+        let field = |key: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                .map(|value| value.trim().trim_matches('"').to_string())
+                .filter(|value| !value.is_empty())
+        };
+        field("PRETTY_NAME").or_else(|| {
+            let name = field("NAME")?;
+            Some(match field("VERSION") {
+                Some(version) => format!("{} {}", name, version),
+                None => name,
+            })
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ContextProvider for DistroContext {
+    fn name(&self) -> &str {
+        "distro"
+    }
+
+    fn description(&self) -> &str {
+        "Operating system distribution and architecture"
+    }
+
+    fn default_budget(&self) -> usize {
+        100
+    }
+
+    async fn collect(&self, _session: &SessionState<'_>) -> Option<String> {
+        let system = Self::os_release().unwrap_or_else(|| std::env::consts::OS.to_string());
+        Some(format!("{} ({})", system, std::env::consts::ARCH))
+    }
+}
+
+/// Every built-in provider, in the order their sections appear in prompts.
+pub fn builtin_providers() -> Vec<Box<dyn ContextProvider>> {
+    vec![
+        Box::new(DistroContext),
+        Box::new(ShellContext),
+        Box::new(ProjectTypeContext),
+        Box::new(VirtualenvContext),
+        Box::new(GitContext),
+        Box::new(RecentCommandsContext::default()),
+        Box::new(LastOutputContext),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    #[tokio::test]
+    async fn virtualenv_comes_from_the_session_shell() {
+        let env = [
+            (OsString::from("PATH"), OsString::from("/venv/bin:/usr/bin")),
+            (OsString::from("VIRTUAL_ENV"), OsString::from("/venv")),
+        ];
+        let mut session = SessionState { cwd: Path::new("/"), recent_commands: &[], last_output: None, env: &env };
+        assert_eq!(VirtualenvContext.collect(&session).await.as_deref(), Some("virtualenv: /venv"));
+
+        // After `deactivate`
+        session.env = &env[..1];
+        assert_eq!(VirtualenvContext.collect(&session).await, None);
+    }
+
+    #[tokio::test]
+    async fn git_reports_the_branch_and_changes() {
+        let dir = std::env::temp_dir().join(format!("dirac-git-context-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let init = std::process::Command::new("git").arg("init").arg("-q").current_dir(&dir).status();
+        if !init.map(|status| status.success()).unwrap_or(false) {
+            // No git on this machine
+            let _ = std::fs::remove_dir_all(&dir);
+            return;
+        }
+        let session = SessionState { cwd: &dir, recent_commands: &[], last_output: None, env: &[] };
+        let clean = GitContext.collect(&session).await.unwrap();
+        assert!(clean.starts_with("branch: ") && clean.ends_with("working tree clean"), "{}", clean);
+
+        std::fs::write(dir.join("notes.txt"), "todo").unwrap();
+        let changed = GitContext.collect(&session).await.unwrap();
+        assert!(changed.ends_with("changes:\n?? notes.txt"), "{}", changed);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+/// Entries listed by name unless configured otherwise.
+pub const DEFAULT_MAX_ENTRIES: usize = 40;
+
+/// Entries read from one directory; anything beyond is only reported as such.
+const MAX_SCANNED: usize = 100_000;
+/// Entries read from each listed subdirectory, for its count and tree, as
+/// that is done for every shown directory on every prompt.
+const MAX_SUBDIRECTORY_SCANNED: usize = 1_000;
+/// Entries whose modification time is looked up, as that costs a `stat` each.
+const MAX_STATTED: usize = 5_000;
+/// Extension groups listed before the rest are lumped together.
+const MAX_EXTENSION_GROUPS: usize = 8;
+/// Subdirectories shown under each directory of the tree.
+const MAX_SUBDIRECTORIES: usize = 4;
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    is_dir: bool,
+    tracked: bool,
+    modified: Option<SystemTime>,
+}
+
+/// Names of the entries of `dir` committed to git, empty outside a repository.
+fn tracked_names(dir: &Path) -> HashSet<String> {
+    Command::new("git")
+        .args(["ls-tree", "--name-only", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn plural(count: usize, one: &str, many: &str) -> String {
+    format!("{} {}", count, if count == 1 { one } else { many })
+}
+
+/// Counts the entries of `dir`, giving up at `MAX_SUBDIRECTORY_SCANNED`.
+fn count_entries(dir: &Path) -> String {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => match entries.take(MAX_SUBDIRECTORY_SCANNED).count() {
+            MAX_SUBDIRECTORY_SCANNED => format!("{}+ entries", MAX_SUBDIRECTORY_SCANNED),
+            count => plural(count, "entry", "entries"),
+        },
+        Err(_) => "unreadable".to_string(),
+    }
+}
+
+fn subdirectories(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .take(MAX_SUBDIRECTORY_SCANNED)
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().map(|kind| kind.is_dir()).unwrap_or(false))
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy().to_lowercase()))
+}
+
+/// Describes a directory for the prompt in a bounded number of lines, however
+/// many entries it has: counts, files grouped by extension, a shallow tree of
+/// subdirectories and the most relevant files by name. Git-tracked and
+/// recently modified entries are listed first.
+#[derive(Debug, Clone)]
+pub struct DirectorySummarizer {
+    max_entries: usize,
+}
+
+impl DirectorySummarizer {
+    /// Lists at most `max_entries` files and directories by name.
+    pub fn new(max_entries: usize) -> Self {
+        Self { max_entries }
+    }
+
+    pub fn summarize(&self, dir: &Path) -> String {
+        let reader = match std::fs::read_dir(dir) {
+            Ok(reader) => reader,
+            Err(e) => return format!("  (unreadable: {})", e),
+        };
+        let tracked = tracked_names(dir);
+
+        let mut entries = Vec::new();
+        let mut unscanned = 0;
+        for entry in reader.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                continue;
+            }
+            if entries.len() == MAX_SCANNED {
+                unscanned += 1;
+                continue;
+            }
+            // The file type usually comes with the directory entry itself,
+            // only symlinks need a lookup
+            let is_dir = match entry.file_type() {
+                Ok(kind) if kind.is_symlink() => entry.path().is_dir(),
+                Ok(kind) => kind.is_dir(),
+                Err(_) => false,
+            };
+            let modified = if entries.len() < MAX_STATTED {
+                entry.metadata().and_then(|m| m.modified()).ok()
+            } else {
+                None
+            };
+            entries.push(Entry {
+                tracked: tracked.contains(&name),
+                is_dir,
+                modified,
+                name,
+            });
+        }
+        if entries.is_empty() {
+            return "  (empty directory)".to_string();
+        }
+
+        // Most relevant first: tracked, then most recently modified, then by name
+        entries.sort_by(|a, b| {
+            b.tracked
+                .cmp(&a.tracked)
+                .then(b.modified.cmp(&a.modified))
+                .then(a.name.cmp(&b.name))
+        });
+        let (dirs, files): (Vec<&Entry>, Vec<&Entry>) = entries.iter().partition(|e| e.is_dir);
+
+        let mut lines = vec![format!("  {}, {}", plural(dirs.len(), "directory", "directories"), plural(files.len(), "file", "files"))];
+        if unscanned > 0 {
+            lines[0].push_str(&format!(" (and {} more entries not inspected)", unscanned));
+        }
+        if let Some(types) = Self::extension_summary(&files) {
+            lines.push(format!("  File types: {}", types));
+        }
+
+        // Directories get at least half of the budget, or whatever the files
+        // leave over; subdirectories only use what the tree has to spare
+        let tree_budget = (self.max_entries / 2).max(self.max_entries.saturating_sub(files.len()));
+        let shown_dirs = dirs.len().min(tree_budget);
+        let mut spare = tree_budget - shown_dirs;
+        let mut tree_lines = 0;
+        if shown_dirs > 0 {
+            lines.push("  Directories:".to_string());
+            for dir_entry in &dirs[..shown_dirs] {
+                let path = dir.join(&dir_entry.name);
+                lines.push(format!("    {}/ ({})", dir_entry.name, count_entries(&path)));
+                tree_lines += 1;
+                if spare == 0 {
+                    continue;
+                }
+                for sub in subdirectories(&path).into_iter().take(MAX_SUBDIRECTORIES.min(spare)) {
+                    lines.push(format!("      {}/", sub));
+                    tree_lines += 1;
+                    spare -= 1;
+                }
+            }
+        }
+        let shown_files = files.len().min(self.max_entries - tree_lines);
+        if shown_files > 0 {
+            lines.push("  Files:".to_string());
+            lines.extend(files[..shown_files].iter().map(|file| format!("    {}", file.name)));
+        }
+
+        let hidden_dirs = dirs.len() - shown_dirs;
+        let hidden_files = files.len() - shown_files;
+        if hidden_dirs + hidden_files > 0 {
+            lines.push(format!(
+                "  ... {} more files and {} more directories not listed",
+                hidden_files, hidden_dirs
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// e.g. `120 .jpg, 30 .png, 2 without extension`, most common first.
+    fn extension_summary(files: &[&Entry]) -> Option<String> {
+        let mut counts: HashMap<Option<String>, usize> = HashMap::new();
+        for file in files {
+            *counts.entry(extension(&file.name)).or_default() += 1;
+        }
+        let mut groups: Vec<(Option<String>, usize)> = counts.into_iter().collect();
+        groups.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut parts: Vec<String> = groups
+            .iter()
+            .take(MAX_EXTENSION_GROUPS)
+            .map(|(ext, count)| match ext {
+                Some(ext) => format!("{} {}", count, ext),
+                None => format!("{} without extension", count),
+            })
+            .collect();
+        let other: usize = groups.iter().skip(MAX_EXTENSION_GROUPS).map(|(_, count)| count).sum();
+        if other > 0 {
+            parts.push(format!("{} other", other));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+impl Default for DirectorySummarizer {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// A directory of its own under the system's temporary one, with `files`
+    /// and `dirs` in it.
+    fn scratch(name: &str, files: &[&str], dirs: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dirac-listing-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        for sub in dirs {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        dir
+    }
+
+    fn names(prefix: &str, count: usize) -> Vec<String> {
+        (0..count).map(|n| format!("{}{:02}", prefix, n)).collect()
+    }
+
+    fn as_strs(names: &[String]) -> Vec<&str> {
+        names.iter().map(String::as_str).collect()
+    }
+
+    /// Lines naming a top-level entry, as opposed to headings and the tree.
+    fn listed(summary: &str) -> Vec<&str> {
+        summary
+            .lines()
+            .filter(|line| line.starts_with("    ") && !line.starts_with("      "))
+            .map(str::trim)
+            .collect()
+    }
+
+    #[test]
+    fn more_entries_than_the_cap_split_it_between_directories_and_files() {
+        let (files, dirs) = (names("file", 30), names("dir", 30));
+        let dir = scratch("capped", &as_strs(&files), &as_strs(&dirs));
+        let summary = DirectorySummarizer::new(10).summarize(&dir);
+
+        assert!(summary.starts_with("  30 directories, 30 files"), "{}", summary);
+        let listed = listed(&summary);
+        assert_eq!(listed.len(), 10, "{}", summary);
+        assert_eq!(listed.iter().filter(|line| line.starts_with("dir")).count(), 5, "{}", summary);
+        assert!(summary.ends_with("... 25 more files and 25 more directories not listed"), "{}", summary);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_get_the_whole_budget_without_directories() {
+        let files = names("file", 20);
+        let dir = scratch("files", &as_strs(&files), &[]);
+        let summary = DirectorySummarizer::new(10).summarize(&dir);
+
+        assert!(!summary.contains("Directories:"), "{}", summary);
+        assert_eq!(listed(&summary).len(), 10, "{}", summary);
+        assert!(summary.ends_with("... 10 more files and 0 more directories not listed"), "{}", summary);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn directories_spend_what_is_left_on_their_subdirectories() {
+        let dirs: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .flat_map(|top| (0..5).map(move |n| format!("{}/sub{}", top, n)))
+            .collect();
+        let dir = scratch("dirs", &[], &as_strs(&dirs));
+        let summary = DirectorySummarizer::new(10).summarize(&dir);
+
+        assert!(!summary.contains("Files:"), "{}", summary);
+        let mut listed = listed(&summary);
+        listed.sort();
+        assert_eq!(listed, ["a/ (5 entries)", "b/ (5 entries)", "c/ (5 entries)"], "{}", summary);
+        // Seven lines to spare: four under the first directory, three under the next
+        let tree = summary.lines().filter(|line| line.starts_with("      ")).count();
+        assert_eq!(tree, 7, "{}", summary);
+        assert!(!summary.contains("not listed"), "{}", summary);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn no_budget_lists_only_the_counts() {
+        let dir = scratch("none", &["a.txt", "b.txt"], &["src"]);
+        let summary = DirectorySummarizer::new(0).summarize(&dir);
+
+        assert!(listed(&summary).is_empty(), "{}", summary);
+        assert_eq!(
+            summary,
+            "  1 directory, 2 files\n  File types: 2 .txt\n  ... 2 more files and 1 more directories not listed"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn recently_modified_files_come_first() {
+        let dir = scratch("recent", &["a.txt", "b.txt", "c.txt"], &[]);
+        let now = SystemTime::now();
+        for (name, age) in [("a.txt", 300), ("b.txt", 10), ("c.txt", 100)] {
+            let file = std::fs::File::options().write(true).open(dir.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        let summary = DirectorySummarizer::new(2).summarize(&dir);
+
+        assert_eq!(listed(&summary), ["b.txt", "c.txt"], "{}", summary);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::services::probe;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command as TokioCommand;
+use tokio::time::timeout;
+
+/// How long `whatis` or `man` may take.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Width man pages are rendered at, wide enough to keep option columns on one line.
+const MAN_WIDTH: &str = "120";
+
+/// What the local system documents about a program: its `whatis` summary
+/// and the text of its man page, or of its `--help` output for programs
+/// without one.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramReference {
+    pub summary: Option<String>,
+    text: Option<String>,
+}
+
+async fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = TokioCommand::new(program)
+        .args(args)
+        .env("MANWIDTH", MAN_WIDTH)
+        .env("MANPAGER", "cat")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = timeout(LOOKUP_TIMEOUT, output).await.ok()?.ok()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Removes the `x\bx` overstrikes man uses for bold and underlined text.
+fn strip_overstrike(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\u{8}' {
+            plain.pop();
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// `--color[=WHEN]`, `-n NUM` and `--lines=<N>` all name the option before
+/// their argument.
+fn option_name(spelling: &str) -> &str {
+    let end = spelling.find(['=', '[', '<']).unwrap_or(spelling.len());
+    &spelling[..end]
+}
+
+impl ProgramReference {
+    /// Looks up a bare program name. Paths and anything else that is not a
+    /// plain name get an empty reference, so no arbitrary file is run.
+    pub async fn lookup(program: &str) -> Self {
+        if !probe::is_program_name(program) {
+            return Self::default();
+        }
+        let summary = run("whatis", &[program]).await.and_then(|text| {
+            let line = text.lines().next()?;
+            let (_, description) = line.split_once(" - ")?;
+            Some(description.trim().to_string())
+        });
+        let text = match summary {
+            Some(_) => run("man", &[program]).await.map(|text| strip_overstrike(&text)),
+            None => None,
+        };
+        // Without a man page, only programs known to just print their usage
+        // are run with `--help`
+        let text = match text {
+            Some(text) => Some(text),
+            None => probe::help(program).await.ok(),
+        };
+        Self { summary, text }
+    }
+
+    /// The description of `flag` in the program's documentation, found by
+    /// its entry in the option list.
+    pub fn describe_flag(&self, flag: &str) -> Option<String> {
+        let flag = option_name(flag);
+        let mut lines = self.text.as_deref()?.lines().peekable();
+        while let Some(line) = lines.next() {
+            let entry = line.trim_start();
+            if !entry.starts_with('-') {
+                continue;
+            }
+            // Option spellings come first, separated from the description
+            // by a run of spaces
+            let (spellings, description) = match entry.find("  ") {
+                Some(gap) => (&entry[..gap], entry[gap..].trim()),
+                None => (entry, ""),
+            };
+            let matches = spellings
+                .split([',', ' '])
+                .filter(|spelling| spelling.starts_with('-'))
+                .any(|spelling| option_name(spelling) == flag);
+            if !matches {
+                continue;
+            }
+            if !description.is_empty() {
+                return Some(description.to_string());
+            }
+            // Man pages often put the description on the next line
+            return lines
+                .peek()
+                .map(|next| next.trim())
+                .filter(|next| !next.is_empty() && !next.starts_with('-'))
+                .map(str::to_string);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_plain_names_are_looked_up() {
+        for program in ["../bin/ls", "/usr/bin/ls", "-k", "ls; true", ""] {
+            let reference = ProgramReference::lookup(program).await;
+            assert!(reference.summary.is_none() && reference.text.is_none(), "{}", program);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::core::lib::{AIProcessor, AIResponse, ChatMessage, DiracError, DiracResult, PromptKind, TerminalInterface};
+use crate::core::suggestion::{Risk, Suggestion};
+use crate::services::prompt::PromptTemplates;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Canned answer returned by `ScriptedAIProcessor`.
+#[derive(Debug, Clone)]
+pub enum ScriptedResponse {
+    Suggestion(Suggestion),
+    /// Raw model output, to exercise handling of malformed answers
+    Raw(String),
+    /// Fails the way an unreachable backend does
+    Unavailable(String),
+    /// Fails the way a backend without the named model does
+    MissingModel(String),
+}
+
+/// A request as received by `ScriptedAIProcessor`.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub kind: PromptKind,
+    pub input: String,
+    pub context: String,
+    pub history: Vec<ChatMessage>,
+    /// The prompt a real backend would have sent for this request
+    pub prompt: String,
+}
+
+/// Offline `AIProcessor` answering from a script of canned responses matched
+/// by request pattern. Every request is recorded; clones share the script
+/// and the recording, so a test can keep a handle after boxing one.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedAIProcessor {
+    rules: Arc<Mutex<Vec<(String, ScriptedResponse)>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    templates: Arc<PromptTemplates>,
+    /// Set for a model that is not in memory yet, as the time loading it takes
+    load_delay: Option<Duration>,
+    status_checks: Arc<AtomicUsize>,
+}
+
+impl ScriptedAIProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers requests containing `pattern` (case-insensitive) with
+    /// `response`. Rules are tried in the order they were added; an empty
+    /// pattern matches every request.
+    pub fn respond(self, pattern: impl Into<String>, response: ScriptedResponse) -> Self {
+        self.rules
+            .lock()
+            .unwrap()
+            .push((pattern.into().to_lowercase(), response));
+        self
+    }
+
+    /// Shorthand for answering with a low-risk suggestion of `command`.
+    pub fn suggest(self, pattern: impl Into<String>, command: &str, explanation: &str) -> Self {
+        self.respond(
+            pattern,
+            ScriptedResponse::Suggestion(Suggestion {
+                command: command.to_string(),
+                explanation: explanation.to_string(),
+                risk: Risk::Low,
+                alternatives: Vec::new(),
+                requires_sudo: false,
+                probes: Vec::new(),
+                steps: Vec::new(),
+            }),
+        )
+    }
+
+    /// Renders recorded prompts with `templates` instead of the built-in ones.
+    pub fn with_templates(mut self, templates: Arc<PromptTemplates>) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Behaves like a backend whose model has to be loaded first: streamed
+    /// answers start after `delay`, and `is_model_loaded` says `false`.
+    pub fn cold(mut self, delay: Duration) -> Self {
+        self.load_delay = Some(delay);
+        self
+    }
+
+    /// How often `is_model_loaded` has been asked.
+    pub fn status_checks(&self) -> usize {
+        self.status_checks.load(Ordering::SeqCst)
+    }
+
+    /// Every request received so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Records the request and returns the raw answer scripted for it.
+    fn answer(&self, kind: PromptKind, input: &str, context: &str, history: &[ChatMessage]) -> DiracResult<String> {
+        self.requests.lock().unwrap().push(RecordedRequest {
+            kind,
+            input: input.to_string(),
+            context: context.to_string(),
+            history: history.to_vec(),
+            prompt: self.templates.render(kind, input, context, history)?,
+        });
+
+        let request = input.to_lowercase();
+        let rules = self.rules.lock().unwrap();
+        let response = rules
+            .iter()
+            .find(|(pattern, _)| request.contains(pattern.as_str()))
+            .map(|(_, response)| response.clone())
+            .ok_or_else(|| {
+                DiracError::AIProcessingError(format!("No scripted response for '{}'", input))
+            })?;
+
+        match response {
+            ScriptedResponse::Suggestion(suggestion) => serde_json::to_string(&suggestion)
+                .map_err(|e| DiracError::AIProcessingError(format!("Failed to encode suggestion: {}", e))),
+            ScriptedResponse::Raw(text) => Ok(text),
+            ScriptedResponse::Unavailable(msg) => Err(DiracError::AIUnavailableError(msg)),
+            ScriptedResponse::MissingModel(model) => Err(DiracError::ModelNotFound(model)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl AIProcessor for ScriptedAIProcessor {
+    fn name(&self) -> String {
+        "scripted".to_string()
+    }
+
+    async fn is_model_loaded(&self) -> Option<bool> {
+        self.status_checks.fetch_add(1, Ordering::SeqCst);
+        Some(self.load_delay.is_none())
+    }
+
+    async fn process<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<Suggestion> {
+        Suggestion::parse(&self.answer(kind, input, context, history)?)
+    }
+
+    async fn process_stream<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<AIResponse> {
+        // Hand out the answer in small pieces, like a model generating tokens
+        let answer: Vec<char> = self.answer(kind, input, context, history)?.chars().collect();
+        if let Some(delay) = self.load_delay {
+            tokio::time::sleep(delay).await;
+        }
+        let chunks: Vec<DiracResult<String>> = answer
+            .chunks(8)
+            .map(|chunk| Ok(chunk.iter().collect()))
+            .collect();
+        Ok(AIResponse {
+            provider: self.name(),
+            cached: false,
+            stream: Box::pin(futures_util::stream::iter(chunks)),
+        })
+    }
+}
+
+/// `TerminalInterface` reading from a fixed list of input lines and
+/// capturing everything displayed. Clones share their state.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedTerminal {
+    inputs: Arc<Mutex<VecDeque<String>>>,
+    history: Arc<Mutex<Vec<String>>>,
+    output: Arc<Mutex<Vec<String>>>,
+    /// Whether the last output line was left open by `display_partial`
+    line_open: Arc<Mutex<bool>>,
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl ScriptedTerminal {
+    pub fn new<I, S>(inputs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let terminal = Self::default();
+        terminal
+            .inputs
+            .lock()
+            .unwrap()
+            .extend(inputs.into_iter().map(Into::into));
+        terminal
+    }
+
+    pub fn history(&self) -> Vec<String> {
+        self.history.lock().unwrap().clone()
+    }
+
+    /// Every line displayed so far, with the pieces shown by
+    /// `display_partial` joined into the lines they were part of.
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+impl TerminalInterface for ScriptedTerminal {
+    fn read_line(&mut self, _prompt: &str) -> DiracResult<String> {
+        self.inputs
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(DiracError::EndOfInput)
+    }
+
+    fn add_history(&mut self, line: &str) {
+        self.history.lock().unwrap().push(line.to_string());
+    }
+
+    fn display_output(&self, output: &str) {
+        self.display_partial(output);
+        *self.line_open.lock().unwrap() = false;
+    }
+
+    fn display_partial(&self, output: &str) {
+        let mut lines = self.output.lock().unwrap();
+        let mut line_open = self.line_open.lock().unwrap();
+        match lines.last_mut() {
+            Some(line) if *line_open => line.push_str(output),
+            _ => lines.push(output.to_string()),
+        }
+        *line_open = true;
+    }
+
+    fn display_error(&self, error: &str) {
+        self.errors.lock().unwrap().push(error.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::lib::ChatRole;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn scripted_processor_answers_from_the_first_matching_rule() {
+        let ai = ScriptedAIProcessor::new()
+            .suggest("list", "ls -la", "Lists files")
+            .suggest("", "echo fallback", "Anything else");
+
+        let suggestion = ai.process(PromptKind::Generate, "List files", "", &[]).await.unwrap();
+        assert_eq!(suggestion.command, "ls -la");
+        let suggestion = ai.process(PromptKind::Generate, "something", "", &[]).await.unwrap();
+        assert_eq!(suggestion.command, "echo fallback");
+    }
+
+    #[tokio::test]
+    async fn scripted_processor_records_requests_across_clones() {
+        let ai = ScriptedAIProcessor::new().suggest("", "true", "");
+        let handle = ai.clone();
+        let history = [ChatMessage::new(ChatRole::User, "earlier")];
+        ai.process(PromptKind::Diagnose, "why", "cwd: /tmp", &history).await.unwrap();
+
+        let requests = handle.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].kind, PromptKind::Diagnose);
+        assert_eq!(requests[0].input, "why");
+        assert_eq!(requests[0].context, "cwd: /tmp");
+        assert_eq!(requests[0].history.len(), 1);
+        assert!(requests[0].prompt.contains("why"));
+    }
+
+    #[tokio::test]
+    async fn scripted_processor_fails_like_a_backend() {
+        let ai = ScriptedAIProcessor::new()
+            .respond("down", ScriptedResponse::Unavailable("offline".to_string()))
+            .respond("garbled", ScriptedResponse::Raw("not json".to_string()));
+
+        assert!(matches!(
+            ai.process(PromptKind::Generate, "down", "", &[]).await,
+            Err(DiracError::AIUnavailableError(_))
+        ));
+        assert!(ai.process(PromptKind::Generate, "garbled", "", &[]).await.is_err());
+        assert!(matches!(
+            ai.process(PromptKind::Generate, "unscripted", "", &[]).await,
+            Err(DiracError::AIProcessingError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn scripted_processor_streams_the_whole_answer() {
+        let ai = ScriptedAIProcessor::new().suggest("", "find . -name '*.rs'", "Finds Rust files");
+        let response = ai.process_stream(PromptKind::Generate, "rust files", "", &[]).await.unwrap();
+        let chunks: Vec<String> = response.stream.map(|chunk| chunk.unwrap()).collect().await;
+        assert!(chunks.len() > 1);
+        let suggestion = Suggestion::parse(&chunks.concat()).unwrap();
+        assert_eq!(suggestion.command, "find . -name '*.rs'");
+    }
+
+    #[test]
+    fn scripted_terminal_reads_its_inputs_then_ends() {
+        let mut terminal = ScriptedTerminal::new(["first", "second"]);
+        assert_eq!(terminal.read_line("> ").unwrap(), "first");
+        assert_eq!(terminal.read_line_with_initial("> ", "ignored").unwrap(), "second");
+        assert!(matches!(terminal.read_line("> "), Err(DiracError::EndOfInput)));
+    }
+
+    #[test]
+    fn scripted_terminal_captures_what_is_displayed() {
+        let mut terminal = ScriptedTerminal::new(Vec::<String>::new());
+        let handle = terminal.clone();
+        terminal.add_history("ls");
+        terminal.display_output("one");
+        terminal.display_partial("tw");
+        terminal.display_partial("o");
+        terminal.display_output("");
+        terminal.display_output("three");
+        terminal.display_error("oops");
+
+        assert_eq!(handle.history(), ["ls"]);
+        assert_eq!(handle.output(), ["one", "two", "three"]);
+        assert_eq!(handle.errors(), ["oops"]);
+    }
+}
@@ -1,5 +1,32 @@
 pub mod ai;
+pub mod cache;
+pub mod capture;
 pub mod command;
+pub mod context;
+#[cfg(feature = "embedded")]
+pub mod embedded;
+pub mod listing;
+pub mod manual;
+pub mod models;
+pub mod mock;
+pub mod openai;
+pub mod probe;
+pub mod prompt;
+pub mod pty;
+pub mod redact;
+pub mod request;
+pub mod router;
+pub mod session;
+#[cfg(feature = "embedded")]
+pub mod tokenizer;
 
 pub use self::ai::OllamaProcessor;
+pub use self::cache::CachedProcessor;
+#[cfg(feature = "embedded")]
+pub use self::embedded::EmbeddedProcessor;
+pub use self::models::OllamaModels;
+pub use self::mock::{ScriptedAIProcessor, ScriptedResponse, ScriptedTerminal};
+pub use self::openai::OpenAIProcessor;
+pub use self::prompt::PromptTemplates;
+pub use self::router::RouterProcessor;
 pub use command::ShellCommandExecutor;
\ No newline at end of file
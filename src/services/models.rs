@@ -0,0 +1,283 @@
+use crate::core::lib::{DiracError, DiracResult};
+use crate::services::ai::{api_error, response_lines};
+use crate::services::request::{RequestPolicy, SendFailure};
+use futures_util::{future, Stream, StreamExt};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// How long `/api/ps` may take; it only decides whether to show a note.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a download may go without progress updates. Verifying the
+/// digest of a large model is silent for a while.
+const PULL_STALL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Size, family and quantization of a model, as reported by Ollama.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelDetails {
+    #[serde(default)]
+    pub format: String,
+    #[serde(default)]
+    pub family: String,
+    #[serde(default)]
+    pub parameter_size: String,
+    #[serde(default)]
+    pub quantization_level: String,
+}
+
+/// An installed model as listed by `/api/tags`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelSummary {
+    pub name: String,
+    /// Bytes on disk
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub modified_at: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
+
+/// What `/api/show` reports about one model.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelInfo {
+    #[serde(default)]
+    pub details: ModelDetails,
+    /// Default generation parameters, one `name value` per line
+    #[serde(default)]
+    pub parameters: String,
+    #[serde(default)]
+    pub license: String,
+    /// Architecture specifics such as `llama.context_length`
+    #[serde(default)]
+    pub model_info: serde_json::Map<String, Value>,
+}
+
+impl ModelInfo {
+    /// Longest prompt the model accepts, in tokens.
+    pub fn context_length(&self) -> Option<u64> {
+        self.model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64())
+    }
+}
+
+/// One status update while a model downloads.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullProgress {
+    pub status: String,
+    /// Layer being downloaded, absent for the other steps
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+}
+
+/// `llama3` and `llama3:latest` name the same model.
+fn canonical_name(name: &str) -> String {
+    if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("{}:latest", name)
+    }
+}
+
+/// Lists, inspects and downloads the models of an Ollama server.
+#[derive(Debug, Clone)]
+pub struct OllamaModels {
+    client: Client,
+    policy: RequestPolicy,
+    base_url: String,
+}
+
+impl OllamaModels {
+    /// Uses the server behind `api_url`, the chat endpoint Dirac talks to.
+    pub fn new(api_url: &str) -> Self {
+        let base_url = match api_url.find("/api/") {
+            Some(index) => &api_url[..index],
+            None => api_url.trim_end_matches('/'),
+        };
+        let policy = RequestPolicy::default();
+        Self {
+            client: policy.client(),
+            policy,
+            base_url: base_url.to_string(),
+        }
+    }
+
+    /// Uses `policy` for timeouts and retries instead of the defaults.
+    pub fn with_policy(mut self, policy: RequestPolicy) -> Self {
+        self.client = policy.client();
+        self.policy = policy;
+        self
+    }
+
+    fn unreachable(&self, failure: SendFailure) -> DiracError {
+        let reason = match failure {
+            SendFailure::Request(e) => e.to_string(),
+            SendFailure::TimedOut(limit) => format!("no answer within {} seconds", limit.as_secs()),
+            SendFailure::Status { status, .. } => status.to_string(),
+            SendFailure::Loading => "still loading".to_string(),
+        };
+        DiracError::AIUnavailableError(format!(
+            "Ollama is not reachable at {}: {}. Start it with 'ollama serve'.",
+            self.base_url, reason
+        ))
+    }
+
+    async fn error_from(response: reqwest::Response, model: &str) -> DiracError {
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return DiracError::ModelNotFound(model.to_string());
+        }
+        let body = response.json::<Value>().await.unwrap_or_default();
+        match body.get("error") {
+            Some(error) => api_error(error, model),
+            None => DiracError::AIProcessingError(format!("Ollama error: {}", status)),
+        }
+    }
+
+    /// Installed models, sorted by name.
+    pub async fn list(&self) -> DiracResult<Vec<ModelSummary>> {
+        let url = format!("{}/api/tags", self.base_url);
+        let response = self
+            .policy
+            .send(|| self.client.get(&url))
+            .await
+            .map_err(|failure| self.unreachable(failure))?;
+        if !response.status().is_success() {
+            return Err(Self::error_from(response, "").await);
+        }
+
+        #[derive(Deserialize)]
+        struct Tags {
+            #[serde(default)]
+            models: Vec<ModelSummary>,
+        }
+        let mut models = response
+            .json::<Tags>()
+            .await
+            .map_err(|e| DiracError::AIProcessingError(format!("Invalid response from Ollama: {}", e)))?
+            .models;
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(models)
+    }
+
+    pub async fn is_installed(&self, name: &str) -> DiracResult<bool> {
+        let name = canonical_name(name);
+        Ok(self.list().await?.iter().any(|model| canonical_name(&model.name) == name))
+    }
+
+    /// Whether `name` is currently loaded in memory, per `/api/ps`.
+    pub async fn is_loaded(&self, name: &str) -> DiracResult<bool> {
+        #[derive(Deserialize)]
+        struct Running {
+            #[serde(default)]
+            models: Vec<ModelSummary>,
+        }
+        // The limit covers reading the body too, as a stalled server would
+        // otherwise hold up the request this note is shown before
+        let request = async {
+            match self.client.get(format!("{}/api/ps", self.base_url)).send().await {
+                Ok(response) if response.status().is_success() => response
+                    .json::<Running>()
+                    .await
+                    .map_err(|e| DiracError::AIProcessingError(format!("Invalid response from Ollama: {}", e))),
+                Ok(response) => Err(Self::error_from(response, name).await),
+                Err(e) => Err(self.unreachable(SendFailure::Request(e))),
+            }
+        };
+        let running = timeout(STATUS_TIMEOUT, request)
+            .await
+            .map_err(|_| self.unreachable(SendFailure::TimedOut(STATUS_TIMEOUT)))??;
+        let name = canonical_name(name);
+        Ok(running.models.iter().any(|model| canonical_name(&model.name) == name))
+    }
+
+    pub async fn show(&self, name: &str) -> DiracResult<ModelInfo> {
+        let url = format!("{}/api/show", self.base_url);
+        let body = json!({ "model": name });
+        let response = self
+            .policy
+            .send(|| self.client.post(&url).json(&body))
+            .await
+            .map_err(|failure| self.unreachable(failure))?;
+        if !response.status().is_success() {
+            return Err(Self::error_from(response, name).await);
+        }
+        response
+            .json::<ModelInfo>()
+            .await
+            .map_err(|e| DiracError::AIProcessingError(format!("Invalid response from Ollama: {}", e)))
+    }
+
+    /// Downloads `name`, yielding progress as Ollama reports it. The stream
+    /// ends after the final `success` status.
+    pub async fn pull(&self, name: &str) -> DiracResult<impl Stream<Item = DiracResult<PullProgress>> + Send> {
+        let url = format!("{}/api/pull", self.base_url);
+        let body = json!({ "model": name, "stream": true });
+        let response = self
+            .policy
+            .send(|| self.client.post(&url).json(&body))
+            .await
+            .map_err(|failure| self.unreachable(failure))?;
+        if !response.status().is_success() {
+            return Err(Self::error_from(response, name).await);
+        }
+
+        let name = name.to_string();
+        Ok(response_lines(response, self.policy.read_timeout.max(PULL_STALL_TIMEOUT)).filter_map(move |line| {
+            let result = match line {
+                Ok(line) if line.is_empty() => None,
+                Ok(line) => match serde_json::from_str::<Value>(&line) {
+                    // An unknown model is reported in the stream, not by status
+                    Ok(update) => match update.get("error") {
+                        Some(error) => Some(Err(DiracError::AIProcessingError(format!(
+                            "Failed to pull '{}': {}",
+                            name,
+                            error.as_str().unwrap_or("unknown error")
+                        )))),
+                        None => Some(serde_json::from_value::<PullProgress>(update).map_err(|e| {
+                            DiracError::AIProcessingError(format!("Invalid response from Ollama: {}", e))
+                        })),
+                    },
+                    Err(e) => Some(Err(DiracError::AIProcessingError(format!("Invalid response from Ollama: {}", e)))),
+                },
+                Err(e) => Some(Err(e)),
+            };
+            future::ready(result)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn status_check_gives_up_on_a_stalled_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Headers promise a body that never comes
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 100\r\n\r\n{")
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let models = OllamaModels::new(&format!("http://{}/api/chat", address));
+        let started = std::time::Instant::now();
+        assert!(models.is_loaded("llama3").await.is_err());
+        assert!(started.elapsed() < STATUS_TIMEOUT + Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::core::lib::{AIProcessor, AIResponse, ChatMessage, DiracError, DiracResult, PromptKind};
+use crate::core::suggestion::Suggestion;
+use crate::services::ai::{chat_messages, response_lines};
+use crate::services::prompt::PromptTemplates;
+use crate::services::request::{RequestPolicy, SendFailure};
+use futures_util::{future, StreamExt};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+pub const DEFAULT_MODEL: &str = "default";
+pub const DEFAULT_API_URL: &str = "http://localhost:8080/v1/chat/completions";
+
+/// Backend for servers speaking the OpenAI `/v1/chat/completions` protocol,
+/// such as llama-server, vLLM, LM Studio and LocalAI.
+#[derive(Debug)]
+pub struct OpenAIProcessor {
+    client: Client,
+    policy: RequestPolicy,
+    model: String,
+    api_url: String,
+    api_key: Option<String>,
+    templates: Arc<PromptTemplates>,
+}
+
+impl OpenAIProcessor {
+    pub fn new(
+        model: impl Into<String>,
+        api_url: impl Into<String>,
+        api_key: Option<String>,
+        templates: Arc<PromptTemplates>,
+    ) -> Self {
+        let policy = RequestPolicy::default();
+        Self {
+            client: policy.client(),
+            policy,
+            model: model.into(),
+            api_url: api_url.into(),
+            api_key,
+            templates,
+        }
+    }
+
+    /// Uses `policy` for timeouts and retries instead of the defaults.
+    pub fn with_policy(mut self, policy: RequestPolicy) -> Self {
+        self.client = policy.client();
+        self.policy = policy;
+        self
+    }
+
+    async fn send(&self, kind: PromptKind, messages: Vec<ChatMessage>, stream: bool) -> DiracResult<reqwest::Response> {
+        let body = json!({
+            "model": self.model,
+            "messages": messages,
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": { "name": "suggestion", "schema": Suggestion::schema(kind) }
+            },
+            "stream": stream
+        });
+        let request = || {
+            let request = self.client.post(&self.api_url).json(&body);
+            match &self.api_key {
+                Some(api_key) => request.bearer_auth(api_key),
+                None => request,
+            }
+        };
+
+        self.policy.send(request).await.map_err(|failure| match failure {
+            SendFailure::Request(e) if e.is_connect() && !e.is_timeout() => DiracError::AIUnavailableError(format!(
+                "Could not connect to the AI service at {}. Please check that the server is running.",
+                self.api_url
+            )),
+            SendFailure::Request(e) if e.is_timeout() => {
+                DiracError::AIUnavailableError("Connection to the AI service timed out. Please check if the service is responding.".to_string())
+            }
+            SendFailure::Request(e) => DiracError::AIProcessingError(format!("Failed to connect to AI service: {}", e)),
+            SendFailure::TimedOut(limit) => DiracError::AIUnavailableError(format!(
+                "The AI service at {} did not answer within {} seconds.",
+                self.api_url,
+                limit.as_secs()
+            )),
+            SendFailure::Status { status, body } => {
+                let body = serde_json::from_str::<Value>(&body).unwrap_or_else(|_| json!({ "error": body }));
+                match self.map_error(status, &body) {
+                    DiracError::AIProcessingError(message) => DiracError::AIUnavailableError(message),
+                    error => error,
+                }
+            }
+            SendFailure::Loading => DiracError::ModelLoading(self.model.clone()),
+        })
+    }
+
+    fn map_error(&self, status: reqwest::StatusCode, body: &Value) -> DiracError {
+        api_error(status, body, &self.model, &self.api_url)
+    }
+}
+
+/// The error a server reported for a request to `model` at `api_url`.
+fn api_error(status: reqwest::StatusCode, body: &Value, model: &str, api_url: &str) -> DiracError {
+    // Servers disagree on the error shape: `{"error": {"message": ..}}` or `{"error": ".."}`
+    let error = body.get("error");
+    let message = error
+        .and_then(|e| e.get("message").or(Some(e)))
+        .and_then(|m| m.as_str())
+        .unwrap_or("Unknown error");
+    let code = error
+        .and_then(|e| e.get("code"))
+        .and_then(|c| c.as_str())
+        .unwrap_or("");
+
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        DiracError::AIProcessingError(
+            "The AI service rejected the request. Check the API key passed with --api-key or OPENAI_API_KEY.".to_string()
+        )
+    } else if code == "model_not_found" {
+        DiracError::ModelNotFound(model.to_string())
+    } else if status == reqwest::StatusCode::NOT_FOUND {
+        // Also what a wrong URL gets, so not taken for a missing model
+        DiracError::AIUnavailableError(format!(
+            "Model '{}' not found on the server at {}: {}",
+            model, api_url, message
+        ))
+    } else {
+        DiracError::AIProcessingError(format!("AI service error ({}): {}", status, message))
+    }
+}
+
+#[async_trait::async_trait]
+impl AIProcessor for OpenAIProcessor {
+    fn name(&self) -> String {
+        format!("openai:{}", self.model)
+    }
+
+    async fn process<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<Suggestion> {
+        let prompt = self.templates.render(kind, input, context, history)?;
+        let response = self.send(kind, chat_messages(prompt, history), false).await?;
+
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| DiracError::AIProcessingError(format!("Failed to read AI response: {}", e)))?;
+
+        let json_response = serde_json::from_str::<Value>(&text)
+            .map_err(|e| DiracError::AIProcessingError(format!("Invalid response from AI service: {}", e)))?;
+
+        if !status.is_success() || json_response.get("error").is_some() {
+            return Err(self.map_error(status, &json_response));
+        }
+
+        let content = json_response
+            .pointer("/choices/0/message/content")
+            .and_then(|c| c.as_str())
+            .unwrap_or("");
+
+        Suggestion::parse(content)
+    }
+
+    async fn process_stream<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<AIResponse> {
+        let prompt = self.templates.render(kind, input, context, history)?;
+        let response = self.send(kind, chat_messages(prompt, history), true).await?;
+
+        // Errors are reported as a plain JSON body rather than as events
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.json::<Value>().await.unwrap_or_default();
+            return Err(self.map_error(status, &body));
+        }
+
+        // Server-sent events: `data: {json}` lines terminated by `data: [DONE]`.
+        // A failure partway reports itself as an event with an `error` field
+        let (model, api_url) = (self.model.clone(), self.api_url.clone());
+        let tokens = response_lines(response, self.policy.read_timeout)
+            .take_while(|line| future::ready(!matches!(line, Ok(line) if line == "data: [DONE]")))
+            .filter_map(move |line| {
+                let result = match line {
+                    Ok(line) => line.strip_prefix("data:").and_then(|data| {
+                        match serde_json::from_str::<Value>(data.trim()) {
+                            Ok(event) if event.get("error").is_some() => Some(Err(api_error(status, &event, &model, &api_url))),
+                            Ok(event) => event
+                                .pointer("/choices/0/delta/content")
+                                .and_then(|c| c.as_str())
+                                .filter(|c| !c.is_empty())
+                                .map(|c| Ok(c.to_string())),
+                            Err(e) => Some(Err(DiracError::AIProcessingError(format!("Invalid response from AI service: {}", e)))),
+                        }
+                    }),
+                    Err(e) => Some(Err(e)),
+                };
+                future::ready(result)
+            });
+
+        Ok(AIResponse {
+            provider: self.name(),
+            cached: false,
+            stream: Box::pin(tokens),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn processor() -> OpenAIProcessor {
+        OpenAIProcessor::new("qwen", DEFAULT_API_URL, None, Arc::new(PromptTemplates::default()))
+    }
+
+    #[test]
+    fn model_not_found_code_names_the_missing_model() {
+        let body = json!({ "error": { "message": "The model `qwen` does not exist", "code": "model_not_found" } });
+        let error = processor().map_error(StatusCode::NOT_FOUND, &body);
+        assert!(matches!(error, DiracError::ModelNotFound(model) if model == "qwen"));
+    }
+
+    #[tokio::test]
+    async fn error_events_end_the_stream_with_the_server_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 65536];
+            let _ = socket.read(&mut request).await;
+            let events = concat!(
+                "data: {\"choices\": [{\"delta\": {\"content\": \"{\\\"command\"}}]}\n\n",
+                "data: {\"error\": {\"message\": \"the server ran out of memory\"}}\n\n",
+            );
+            let response = format!("HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n{}", events);
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let url = format!("http://{}/v1/chat/completions", address);
+        let processor = OpenAIProcessor::new("qwen", url, None, Arc::new(PromptTemplates::default()));
+        let response = processor.process_stream(PromptKind::Generate, "list files", "", &[]).await.unwrap();
+        let events: Vec<DiracResult<String>> = response.stream.collect().await;
+        assert!(matches!(&events[0], Ok(text) if text == "{\"command"), "{:?}", events);
+        assert!(matches!(&events[1], Err(e) if e.to_string().contains("the server ran out of memory")), "{:?}", events);
+    }
+
+    #[test]
+    fn bare_not_found_is_not_taken_for_a_missing_model() {
+        let error = processor().map_error(StatusCode::NOT_FOUND, &json!({ "error": "404 page not found" }));
+        assert!(matches!(error, DiracError::AIUnavailableError(_)), "{:?}", error);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::core::suggestion::{Probe, ProbeTool};
+use crate::services::redact::redact;
+use std::io::Read;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tokio::process::Command as TokioCommand;
+use tokio::time::timeout;
+
+/// Most characters of a probe result sent back to the model.
+pub const MAX_RESULT_CHARS: usize = 2000;
+/// Most entries reported by `list_dir`.
+const MAX_DIR_ENTRIES: usize = 100;
+/// Lines shown by `head`.
+const HEAD_LINES: usize = 20;
+/// Most bytes `head` reads; only the start matters.
+const HEAD_BYTES: u64 = MAX_RESULT_CHARS as u64 * 4;
+/// How long `--help` of a program may take.
+const HELP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Programs whose `--help` only prints usage. Others may not know the flag
+/// and do their usual work instead, or treat it as a file name.
+const HELP_PROGRAMS: &[&str] = &[
+    "awk", "basename", "cargo", "cat", "chmod", "chown", "cp", "curl", "cut", "date", "df", "diff",
+    "dirname", "docker", "du", "env", "file", "find", "gawk", "git", "grep", "gzip", "head", "jq",
+    "kill", "kubectl", "ln", "ls", "make", "mkdir", "mv", "node", "npm", "pip", "pip3", "ps",
+    "python", "python3", "rg", "rm", "rmdir", "rsync", "rustc", "rustup", "scp", "sed", "sort",
+    "ssh", "stat", "tail", "tar", "tee", "touch", "tr", "uname", "uniq", "unzip", "wc", "wget",
+    "xargs", "zip",
+];
+
+/// Paths no probe looks at, even inside the working directory. Directory
+/// patterns end in `/` and also match the directory itself.
+const SENSITIVE_PATTERNS: &[&str] = &[
+    ".ssh/", ".gnupg/", ".aws/", ".azure/", ".docker/", ".kube/", ".config/gcloud/", ".netrc",
+    ".npmrc", ".pypirc", ".env", "kubeconfig", "id_rsa", "id_ed25519", "id_ecdsa", ".pem", ".key",
+    "credentials", "shadow", "secret", "token",
+];
+
+/// Resolves `argument` against `cwd`, refusing anything outside the working
+/// directory: absolute paths, `~` and `..` that lead elsewhere, directly or
+/// through a symlink, as the results may go to a remote backend. With
+/// `follow_links` unset, a final symlink is kept rather than followed.
+fn resolve(cwd: &Path, argument: &str, follow_links: bool) -> Result<PathBuf, String> {
+    let root = cwd.canonicalize().map_err(|e| e.to_string())?;
+    let path = cwd.join(argument);
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !follow_links => parent.canonicalize().map(|parent| parent.join(name)),
+        _ => path.canonicalize(),
+    }
+    .map_err(|e| e.to_string())?;
+    let inside = resolved.strip_prefix(&root).map_err(|_| "outside the working directory".to_string())?;
+    if is_sensitive(inside) {
+        return Err("refusing to look at a path that may hold secrets".to_string());
+    }
+    Ok(resolved)
+}
+
+fn is_sensitive(path: &Path) -> bool {
+    let path = format!("{}/", path.to_string_lossy().to_lowercase());
+    SENSITIVE_PATTERNS.iter().any(|pattern| path.contains(pattern))
+}
+
+/// A bare program name, so `help` cannot be pointed at arbitrary paths or
+/// smuggle in shell syntax.
+pub(crate) fn is_program_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+        && !name.starts_with('.')
+        && !name.starts_with('-')
+}
+
+fn list_dir(path: &Path) -> Result<String, String> {
+    let mut entries: Vec<String> = std::fs::read_dir(path)
+        .map_err(|e| e.to_string())?
+        .filter_map(|e| e.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => format!("{}/", name),
+                Ok(kind) if kind.is_symlink() => format!("{}@", name),
+                _ => name,
+            }
+        })
+        .collect();
+    entries.sort();
+    let total = entries.len();
+    entries.truncate(MAX_DIR_ENTRIES);
+    if total > MAX_DIR_ENTRIES {
+        entries.push(format!("... and {} more", total - MAX_DIR_ENTRIES));
+    }
+    if entries.is_empty() {
+        return Ok("(empty directory)".to_string());
+    }
+    Ok(entries.join("\n"))
+}
+
+/// Describes `path` itself, so a symlink's target is named but not examined.
+fn stat(path: &Path) -> Result<String, String> {
+    let metadata = std::fs::symlink_metadata(path).map_err(|e| e.to_string())?;
+    let kind = if metadata.file_type().is_symlink() {
+        match std::fs::read_link(path) {
+            Ok(target) => format!("symlink to {}", target.display()),
+            Err(_) => "symlink".to_string(),
+        }
+    } else if metadata.is_dir() {
+        "directory".to_string()
+    } else {
+        "file".to_string()
+    };
+    let mut lines = vec![
+        format!("type: {}", kind),
+        format!("size: {} bytes", metadata.len()),
+        format!("permissions: {:o}", metadata.permissions().mode() & 0o7777),
+    ];
+    if let Ok(age) = metadata.modified().map(|time| SystemTime::now().duration_since(time)) {
+        lines.push(format!("modified: {} seconds ago", age.unwrap_or_default().as_secs()));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn head(path: &Path) -> Result<String, String> {
+    // Not blocking on the open of a FIFO before it can be turned down
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    if !file.metadata().map_err(|e| e.to_string())?.is_file() {
+        return Err("not a regular file".to_string());
+    }
+    let mut bytes = Vec::new();
+    file.take(HEAD_BYTES).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    if bytes.contains(&0) {
+        return Err("binary file".to_string());
+    }
+    Ok(String::from_utf8_lossy(&bytes)
+        .lines()
+        .take(HEAD_LINES)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn which_program(name: &str) -> Result<String, String> {
+    which::which(name)
+        .map(|path| path.display().to_string())
+        .map_err(|_| format!("{} is not installed or not on the PATH", name))
+}
+
+pub(crate) async fn help(name: &str) -> Result<String, String> {
+    if !is_program_name(name) {
+        return Err("expected a bare program name".to_string());
+    }
+    if !HELP_PROGRAMS.contains(&name) {
+        return Err(format!("'{} --help' is not known to be safe to run", name));
+    }
+    let program = which::which(name).map_err(|_| format!("{} is not installed or not on the PATH", name))?;
+    let output = TokioCommand::new(program)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = timeout(HELP_TIMEOUT, output)
+        .await
+        .map_err(|_| format!("'{} --help' did not finish within {} seconds", name, HELP_TIMEOUT.as_secs()))?
+        .map_err(|e| e.to_string())?;
+    // Plenty of programs print their usage to stderr
+    let text = if output.stdout.is_empty() { &output.stderr } else { &output.stdout };
+    let text = String::from_utf8_lossy(text).trim_end().to_string();
+    if text.is_empty() {
+        return Err(format!("'{} --help' printed nothing", name));
+    }
+    Ok(text)
+}
+
+/// Runs one probe from `cwd`. Only the inspections of `ProbeTool` exist, so
+/// nothing the model asks for can modify the system, and paths stay within
+/// `cwd`. Failures are reported as text for the model to read.
+pub async fn run_probe(probe: &Probe, cwd: &Path) -> String {
+    let argument = probe.argument.as_str();
+    let result = match probe.tool {
+        ProbeTool::ListDir => resolve(cwd, if argument.is_empty() { "." } else { argument }, true).and_then(|path| list_dir(&path)),
+        ProbeTool::Stat => resolve(cwd, argument, false).and_then(|path| stat(&path)),
+        ProbeTool::Head => resolve(cwd, argument, true).and_then(|path| head(&path)),
+        ProbeTool::Which => which_program(argument),
+        ProbeTool::Help => help(argument).await,
+    };
+    // The result goes to the model, which must not see secrets in it
+    let text = redact(&result.unwrap_or_else(|e| format!("error: {}", e)));
+    if text.chars().count() <= MAX_RESULT_CHARS {
+        return text;
+    }
+    let truncated: String = text.chars().take(MAX_RESULT_CHARS).collect();
+    format!("{}\n[truncated]", truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own under the system's temporary one.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dirac-probe-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn head_refuses_devices_and_fifos() {
+        assert_eq!(head(Path::new("/dev/zero")), Err("not a regular file".to_string()));
+        let fifo = scratch("fifo").join("pipe");
+        let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+        assert_eq!(head(&fifo), Err("not a regular file".to_string()));
+        let _ = std::fs::remove_dir_all(fifo.parent().unwrap());
+    }
+
+    #[test]
+    fn head_reads_only_the_first_lines() {
+        let file = scratch("head").join("long.txt");
+        let text: String = (0..100_000).map(|n| format!("line {}\n", n)).collect();
+        std::fs::write(&file, text).unwrap();
+        let shown = head(&file).unwrap();
+        assert_eq!(shown.lines().count(), HEAD_LINES);
+        assert!(shown.starts_with("line 0\nline 1\n"));
+        let _ = std::fs::remove_dir_all(file.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn help_only_runs_known_programs() {
+        assert!(help("shutdown").await.unwrap_err().contains("not known to be safe"));
+        assert!(help("../bin/ls").await.unwrap_err().contains("bare program name"));
+    }
+
+    fn probe(tool: ProbeTool, argument: &str) -> Probe {
+        Probe { tool, argument: argument.to_string() }
+    }
+
+    #[tokio::test]
+    async fn probes_stay_inside_the_working_directory() {
+        let dir = scratch("confined");
+        std::fs::create_dir(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        std::os::unix::fs::symlink("/etc", dir.join("etc")).unwrap();
+        let cwd = dir.join("src");
+
+        assert_eq!(run_probe(&probe(ProbeTool::Head, "main.rs"), &cwd).await, "fn main() {}");
+        for (tool, argument) in [
+            (ProbeTool::Head, "/etc/hostname"),
+            (ProbeTool::Head, "../etc/hostname"),
+            (ProbeTool::ListDir, ".."),
+            (ProbeTool::ListDir, "~"),
+            (ProbeTool::Stat, "/etc/passwd"),
+        ] {
+            let result = run_probe(&probe(tool, argument), &cwd).await;
+            assert!(result.starts_with("error: "), "{} gave {}", argument, result);
+        }
+        // A symlink is described, but not followed out of the directory
+        assert!(run_probe(&probe(ProbeTool::Stat, "etc"), &dir).await.starts_with("type: symlink to /etc"));
+        assert_eq!(run_probe(&probe(ProbeTool::ListDir, "etc"), &dir).await, "error: outside the working directory");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn no_probe_looks_at_secrets() {
+        let dir = scratch("secrets");
+        std::fs::create_dir_all(dir.join(".ssh")).unwrap();
+        std::fs::write(dir.join(".ssh/id_ed25519"), "key").unwrap();
+        std::fs::create_dir_all(dir.join(".docker")).unwrap();
+        std::fs::write(dir.join(".docker/config.json"), r#"{"auths": {}}"#).unwrap();
+
+        for (tool, argument) in [
+            (ProbeTool::ListDir, ".ssh"),
+            (ProbeTool::Stat, ".ssh/id_ed25519"),
+            (ProbeTool::Head, ".docker/config.json"),
+            (ProbeTool::ListDir, ".docker/"),
+        ] {
+            let result = run_probe(&probe(tool, argument), &dir).await;
+            assert_eq!(result, "error: refusing to look at a path that may hold secrets", "{}", argument);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn probe_results_are_redacted() {
+        let dir = scratch("redact");
+        std::fs::write(dir.join("app.conf"), "user=bob\npassword=hunter2\n").unwrap();
+        assert_eq!(run_probe(&probe(ProbeTool::Head, "app.conf"), &dir).await, "user=bob\npassword=[REDACTED]");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
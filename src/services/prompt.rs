@@ -0,0 +1,140 @@
+use crate::core::lib::{ChatMessage, ChatRole, DiracError, DiracResult, PromptKind};
+use crate::core::suggestion::{MAX_ALTERNATIVES, MAX_PLAN_STEPS, MAX_PROBES};
+use crate::services::listing::DirectorySummarizer;
+use minijinja::{context, Environment, UndefinedBehavior};
+use std::path::{Path, PathBuf};
+
+/// Extension of template files in the prompt directory.
+pub const TEMPLATE_EXTENSION: &str = "jinja";
+
+fn builtin_source(kind: PromptKind) -> &'static str {
+    match kind {
+        PromptKind::Generate => include_str!("prompts/generate.jinja"),
+        PromptKind::Diagnose => include_str!("prompts/diagnose.jinja"),
+        PromptKind::Explain => include_str!("prompts/explain.jinja"),
+        PromptKind::Plan => include_str!("prompts/plan.jinja"),
+        PromptKind::Ask => include_str!("prompts/ask.jinja"),
+    }
+}
+
+fn os_type() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "windows"
+    } else if cfg!(target_os = "macos") {
+        "macos"
+    } else {
+        "linux"
+    }
+}
+
+fn history_transcript(history: &[ChatMessage]) -> String {
+    history
+        .iter()
+        .map(|message| {
+            let speaker = match message.role {
+                ChatRole::User => "User",
+                ChatRole::Assistant => "Assistant",
+            };
+            format!("{}: {}", speaker, message.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Prompt templates, one per `PromptKind`. A template is read from
+/// `<dir>/<kind>.jinja` when that file exists and is built in otherwise.
+///
+/// Templates use Jinja syntax and can refer to `{{ request }}`,
+/// `{{ context }}`, `{{ cwd }}`, `{{ os }}`, `{{ dir_listing }}`,
+/// `{{ history }}`, `{{ max_alternatives }}`, `{{ max_probes }}` and
+/// `{{ max_steps }}`.
+#[derive(Debug)]
+pub struct PromptTemplates {
+    env: Environment<'static>,
+    summarizer: DirectorySummarizer,
+}
+
+impl PromptTemplates {
+    /// The templates shipped with Dirac.
+    pub fn builtin() -> Self {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        for kind in PromptKind::ALL {
+            env.add_template(kind.name(), builtin_source(kind))
+                .expect("built-in prompt templates are valid");
+        }
+        Self {
+            env,
+            summarizer: DirectorySummarizer::default(),
+        }
+    }
+
+    /// Lists at most `max_entries` files and directories by name in
+    /// `{{ dir_listing }}`.
+    pub fn with_max_dir_entries(mut self, max_entries: usize) -> Self {
+        self.summarizer = DirectorySummarizer::new(max_entries);
+        self
+    }
+
+    /// `$XDG_CONFIG_HOME/dirac/prompts` or its platform equivalent.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("dirac").join("prompts"))
+    }
+
+    /// Loads the templates found in `dir`, falling back to the built-in ones
+    /// for missing files. A missing directory is not an error.
+    pub fn load(dir: &Path) -> DiracResult<Self> {
+        let mut templates = Self::builtin();
+        for kind in PromptKind::ALL {
+            let path = dir.join(kind.name()).with_extension(TEMPLATE_EXTENSION);
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(DiracError::InputError(format!(
+                        "Failed to read prompt template {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            };
+            templates
+                .env
+                .add_template_owned(kind.name(), source)
+                .map_err(|e| {
+                    DiracError::InputError(format!("Invalid prompt template {}: {}", path.display(), e))
+                })?;
+        }
+        Ok(templates)
+    }
+
+    /// Renders the prompt of `kind` for the current environment.
+    pub fn render(&self, kind: PromptKind, input: &str, context: &str, history: &[ChatMessage]) -> DiracResult<String> {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        let dir_listing = self.summarizer.summarize(&current_dir);
+        self.env
+            .get_template(kind.name())
+            .and_then(|template| {
+                template.render(context! {
+                    request => input,
+                    context => context,
+                    cwd => current_dir.display().to_string(),
+                    os => os_type(),
+                    dir_listing => dir_listing,
+                    history => history_transcript(history),
+                    max_alternatives => MAX_ALTERNATIVES,
+                    max_probes => MAX_PROBES,
+                    max_steps => MAX_PLAN_STEPS,
+                })
+            })
+            .map_err(|e| {
+                DiracError::AIProcessingError(format!("Failed to render the {} prompt: {}", kind.name(), e))
+            })
+    }
+}
+
+impl Default for PromptTemplates {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
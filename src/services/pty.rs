@@ -0,0 +1,597 @@
+use crate::services::capture::Capture;
+use crate::services::command::Foreground;
+use crate::services::session::{self, Fence, Sentinel, ShellState, StatusReader};
+use portable_pty::{native_pty_system, MasterPty, PtySize};
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::process::{ChildStdin, Command, Stdio};
+use std::io::{IsTerminal, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+
+/// How often keyboard input is checked while a command runs, which is also
+/// how long it takes to stop forwarding it once the command is done.
+const INPUT_POLL_MILLIS: i32 = 50;
+
+/// How long output still queued in the terminal is waited for once the
+/// command has exited. Background processes it started may keep the
+/// terminal open for much longer.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What became of a command run in a pseudo-terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PtyStatus {
+    Exited {
+        /// `None` if the command was killed by a signal
+        exit_code: Option<i32>,
+        /// Name of that signal, e.g. `Interrupt`
+        signal: Option<String>,
+    },
+    /// Stopped by CTRL-Z or a signal, until it is continued
+    Stopped,
+}
+
+/// The result of running a command in the foreground until it exits or
+/// stops.
+#[derive(Debug)]
+pub struct PtyRun {
+    pub status: PtyStatus,
+    /// Whether CTRL-C or CTRL-\ was pressed while it ran
+    pub interrupted: bool,
+    /// The start and the end of what the command printed so far, stderr
+    /// included, without escape sequences
+    pub output: String,
+    /// Where the shell was left, for a command run in a session shell that
+    /// is still there
+    pub state: Option<ShellState>,
+}
+
+/// Whether Dirac reads from and writes to a terminal, so commands can be
+/// given one too. Piped input and output keep the plain capture.
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Size of Dirac's own terminal, 80x24 if it cannot be read.
+fn terminal_size() -> PtySize {
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    let read = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if !read || size.ws_row == 0 || size.ws_col == 0 {
+        return PtySize { rows: 24, cols: 80, ..PtySize::default() };
+    }
+    PtySize { rows: size.ws_row, cols: size.ws_col, pixel_width: size.ws_xpixel, pixel_height: size.ws_ypixel }
+}
+
+/// Puts the terminal in raw mode, so every key goes to the command as is,
+/// CTRL-C included, and restores the previous mode, the one rustyline
+/// leaves between lines, when dropped.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return None;
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(RawMode(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+/// Notes CTRL-C and CTRL-\ typed at the command, which the terminal turns
+/// into signals by itself. Returns whether `keys` should still be passed
+/// on: a repeated CTRL-C kills the command instead.
+fn note_interrupts(keys: &[u8], master: Option<RawFd>, foreground: &Foreground) -> bool {
+    let Some(master) = master else {
+        return true;
+    };
+    let mut mode = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(master, &mut mode) } != 0 || mode.c_lflag & libc::ISIG == 0 {
+        return true;
+    }
+    if keys.contains(&mode.c_cc[libc::VQUIT]) {
+        foreground.escalate(libc::SIGQUIT);
+    }
+    if !keys.contains(&mode.c_cc[libc::VINTR]) {
+        return true;
+    }
+    // Programs reading keys one by one, such as REPLs, handle CTRL-C
+    // themselves and are only killed by a repeat while in line mode
+    if mode.c_lflag & libc::ICANON == 0 {
+        foreground.handled();
+        return true;
+    }
+    if foreground.escalate(libc::SIGINT) == libc::SIGKILL {
+        foreground.kill(libc::SIGKILL);
+        return false;
+    }
+    true
+}
+
+/// Copies keyboard input to the command until `stop` is set. Polls rather
+/// than blocking in `read`, which would swallow the first key typed at the
+/// next prompt.
+fn forward_input(writer: Arc<Mutex<Box<dyn Write + Send>>>, master: Option<RawFd>, foreground: Arc<Foreground>, stop: Arc<AtomicBool>) {
+    let mut buffer = [0u8; 1024];
+    while !stop.load(Ordering::SeqCst) {
+        let mut poll = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut poll, 1, INPUT_POLL_MILLIS) } <= 0 {
+            continue;
+        }
+        let read = unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if read <= 0 {
+            break;
+        }
+        let keys = &buffer[..read as usize];
+        if !note_interrupts(keys, master, &foreground) {
+            continue;
+        }
+        let written = writer.lock().map_err(|_| ()).and_then(|mut writer| writer.write_all(keys).map_err(|_| ()));
+        if written.is_err() {
+            break;
+        }
+    }
+}
+
+/// Copies the command's output to the screen as it comes and keeps a
+/// bounded copy. With a `fence`, the sentinel after each command run in a
+/// session shell is left out and reported instead.
+fn forward_output(
+    mut reader: Box<dyn Read + Send>,
+    captured: Arc<Mutex<Capture>>,
+    done: oneshot::Sender<()>,
+    mut fence: Option<(Fence, mpsc::UnboundedSender<()>)>,
+) {
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 4096];
+    // Ends with EIO once every process holding the terminal has exited
+    while let Ok(read) = reader.read(&mut buffer) {
+        if read == 0 {
+            break;
+        }
+        let chunk = match &mut fence {
+            Some((fence, fenced)) => {
+                let (chunk, found) = fence.push(&buffer[..read]);
+                if found {
+                    let _ = fenced.send(());
+                }
+                chunk
+            }
+            None => buffer[..read].to_vec(),
+        };
+        let _ = stdout.write_all(&chunk);
+        let _ = stdout.flush();
+        if let Ok(mut captured) = captured.lock() {
+            captured.push(&chunk);
+        }
+    }
+    let _ = done.send(());
+}
+
+/// The text a terminal would show for `bytes`, roughly: colors, cursor
+/// movement and other escape sequences are dropped, and a carriage return
+/// starts its line over, as progress bars expect.
+pub fn plain_text(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters up to a final byte such as `m` or `H`
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, e.g. a window title: up to BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' {
+                            chars.next_if_eq(&'\\');
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\x08' => {
+                plain.pop();
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' => {
+                let line_start = plain.rfind('\n').map_or(0, |newline| newline + 1);
+                plain.truncate(line_start);
+            }
+            '\n' | '\t' => plain.push(c),
+            c if c.is_control() => {}
+            c => plain.push(c),
+        }
+    }
+    plain
+}
+
+fn signal_name(signal: i32) -> String {
+    let name = unsafe { libc::strsignal(signal) };
+    if name.is_null() {
+        return format!("signal {}", signal);
+    }
+    unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().to_string()
+}
+
+/// Reads a status from `waitpid`, `None` for a continued process.
+fn decode_status(status: i32) -> Option<PtyStatus> {
+    if libc::WIFSTOPPED(status) {
+        Some(PtyStatus::Stopped)
+    } else if libc::WIFEXITED(status) {
+        Some(PtyStatus::Exited { exit_code: Some(libc::WEXITSTATUS(status)), signal: None })
+    } else if libc::WIFSIGNALED(status) {
+        Some(PtyStatus::Exited { exit_code: None, signal: Some(signal_name(libc::WTERMSIG(status))) })
+    } else {
+        None
+    }
+}
+
+/// Runs in the forked child before `exec`: makes it lead a new session on
+/// the pseudo-terminal, its stdout, then forks again, the command going on in a process
+/// group of its own while this process stays behind as its leader. The
+/// kernel ignores CTRL-Z for a group whose parent is in another session, as
+/// Dirac would be; with the leader as parent, the command can be stopped.
+/// Only calls that are safe between `fork` and `exec` are made.
+fn lead_session() -> std::io::Result<()> {
+    unsafe {
+        if libc::setsid() == -1 || libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY as _, 0) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        match libc::fork() {
+            -1 => Err(std::io::Error::last_os_error()),
+            0 => {
+                // Taking over the terminal from the leader's group
+                libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::setpgid(0, 0);
+                libc::tcsetpgrp(libc::STDOUT_FILENO, libc::getpid());
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+                Ok(())
+            }
+            command => relay(command),
+        }
+    }
+}
+
+/// The leader's part, which Dirac waits for in place of the command: it
+/// stops whenever the command stops, continuing it in turn, and exits the
+/// way the command exits.
+unsafe fn relay(command: libc::pid_t) -> ! {
+    // Among them the pipe that tells Dirac the command has started
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+    for fd in 3..limit.rlim_cur.min(65536) as i32 {
+        libc::close(fd);
+    }
+    let no_core = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+
+    loop {
+        let mut status = 0;
+        if libc::waitpid(command, &mut status, libc::WUNTRACED) == -1 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            libc::_exit(1);
+        }
+        if libc::WIFSTOPPED(status) {
+            libc::raise(libc::SIGSTOP);
+            libc::kill(-command, libc::SIGCONT);
+        } else if libc::WIFEXITED(status) {
+            libc::_exit(libc::WEXITSTATUS(status));
+        } else if libc::WIFSIGNALED(status) {
+            let signal = libc::WTERMSIG(status);
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+            libc::_exit(128 + signal);
+        }
+    }
+}
+
+/// The pipes of a shell kept between commands, see
+/// `PtyProcess::spawn_session`.
+struct TerminalSession {
+    /// Where commands are sent, `None` once the shell is to exit after the
+    /// one it runs
+    script: Option<ChildStdin>,
+    status: StatusReader,
+    sentinel: Sentinel,
+    /// Path of the pseudo-terminal, the input of each command
+    tty: String,
+    /// Told each time the output of a command has ended
+    fenced: mpsc::UnboundedReceiver<()>,
+}
+
+/// A command running with `shell -c` in a pseudo-terminal of its own, so
+/// editors, pagers, REPLs and password prompts work, or a session shell
+/// running one command after another there. Its output is shown as it
+/// comes, whether it runs in the foreground or not, but only gets keyboard
+/// input while attached.
+pub struct PtyProcess {
+    master: Box<dyn MasterPty + Send>,
+    /// Kept for as long as the command runs, as dropping it sends
+    /// end-of-file
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// The session leader waiting for the command, see `lead_session`
+    pid: i32,
+    captured: Arc<Mutex<Capture>>,
+    drained: Option<oneshot::Receiver<()>>,
+    session: Option<TerminalSession>,
+}
+
+impl fmt::Debug for PtyProcess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PtyProcess").field("pid", &self.pid).finish_non_exhaustive()
+    }
+}
+
+/// The next report of a session shell, `None` once it has exited.
+async fn next_report(session: &mut Option<TerminalSession>) -> Option<ShellState> {
+    session.as_mut()?.status.next().await.ok().flatten()
+}
+
+impl PtyProcess {
+    /// Starts `command` in `cwd` with exactly the variables in `env`.
+    pub fn spawn(shell: &str, command: &str, cwd: &str, env: &[(OsString, OsString)]) -> Result<Self, String> {
+        Self::start(shell, Some(command), cwd, env)
+    }
+
+    /// Starts a shell reading commands from a pipe instead, each sent with
+    /// `run`. As it stays between them, what they change in it lasts: the
+    /// directory, exported variables, aliases and functions.
+    pub fn spawn_session(shell: &str, cwd: &str, env: &[(OsString, OsString)]) -> Result<Self, String> {
+        Self::start(shell, None, cwd, env)
+    }
+
+    fn start(shell: &str, command: Option<&str>, cwd: &str, env: &[(OsString, OsString)]) -> Result<Self, String> {
+        let pair = native_pty_system().openpty(terminal_size()).map_err(|e| e.to_string())?;
+        let tty = pair.master.tty_name().ok_or("the pseudo-terminal has no name")?;
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&tty)
+            .map_err(|e| e.to_string())?;
+        let stdio = || slave.try_clone().map(Stdio::from).map_err(|e| e.to_string());
+
+        let mut builder = Command::new(shell);
+        builder
+            .current_dir(cwd)
+            .env_clear()
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .env("TERM", env::var("TERM").unwrap_or_else(|_| String::from("xterm-256color")))
+            .env("SHELL", shell)
+            .stdout(stdio()?)
+            .stderr(stdio()?);
+        let session_pipes = match command {
+            Some(command) => {
+                builder.args(["-c", command]).stdin(stdio()?);
+                unsafe { builder.pre_exec(lead_session) };
+                None
+            }
+            None => {
+                let sentinel = Sentinel::new(shell);
+                let (status, status_fd) = session::status_pipe(&sentinel).map_err(|e| e.to_string())?;
+                let passed_fd = status_fd.as_raw_fd();
+                builder.stdin(Stdio::piped());
+                unsafe {
+                    builder.pre_exec(move || {
+                        session::pass_status(passed_fd)?;
+                        lead_session()
+                    })
+                };
+                Some((sentinel, status, status_fd))
+            }
+        };
+        let mut child = builder.spawn().map_err(|e| e.to_string())?;
+        let pid = child.id() as i32;
+        // Only the command may hold the terminal open, or its end goes unnoticed
+        drop(builder);
+        drop(slave);
+        drop(pair.slave);
+        let master: Box<dyn MasterPty + Send> = pair.master;
+
+        let (fence, session) = match session_pipes {
+            Some((sentinel, status, status_fd)) => {
+                drop(status_fd);
+                let mut script = child.stdin.take().ok_or("the shell has no stdin")?;
+                script.write_all(session::STARTUP.as_bytes()).map_err(|e| e.to_string())?;
+                let (fenced_tx, fenced) = mpsc::unbounded_channel();
+                let session = TerminalSession {
+                    script: Some(script),
+                    status,
+                    sentinel: sentinel.clone(),
+                    tty: tty.to_string_lossy().to_string(),
+                    fenced,
+                };
+                (Some((Fence::new(&sentinel), fenced_tx)), Some(session))
+            }
+            None => (None, None),
+        };
+
+        let reader = master.try_clone_reader().map_err(|e| e.to_string())?;
+        let writer = master.take_writer().map_err(|e| e.to_string())?;
+        let captured = Arc::new(Mutex::new(Capture::new()));
+        let (drained_tx, drained_rx) = oneshot::channel();
+        let output_captured = Arc::clone(&captured);
+        thread::spawn(move || forward_output(reader, output_captured, drained_tx, fence));
+
+        Ok(Self {
+            master,
+            writer: Arc::new(Mutex::new(writer)),
+            pid,
+            captured,
+            drained: Some(drained_rx),
+            session,
+        })
+    }
+
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// The start and the end of what the command printed so far.
+    pub fn output(&self) -> String {
+        self.captured.lock().map(|captured| captured.text(plain_text)).unwrap_or_default()
+    }
+
+    /// Continues the command after it was stopped.
+    pub fn resume(&self) {
+        unsafe { libc::kill(-self.pid, libc::SIGCONT) };
+    }
+
+    /// Whether the command exited or stopped since last asked, without
+    /// waiting for it.
+    pub fn try_wait(&self) -> Option<PtyStatus> {
+        let mut status = 0;
+        match unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG | libc::WUNTRACED) } {
+            0 => None,
+            -1 => Some(PtyStatus::Exited { exit_code: None, signal: None }),
+            _ => decode_status(status),
+        }
+    }
+
+    /// Sends `command` to the session shell, with the pseudo-terminal as
+    /// its input if `interactive` is set and none otherwise.
+    fn send(&mut self, command: &str, interactive: bool) -> Result<(), String> {
+        let session = self.session.as_mut().ok_or("no shell is waiting for commands")?;
+        // Left over from a command whose output ended too late
+        while session.fenced.try_recv().is_ok() {}
+        let stdin = if interactive { session.tty.as_str() } else { "/dev/null" };
+        let script = session.sentinel.script(command, stdin, false);
+        let writer = session.script.as_mut().ok_or("no shell is waiting for commands")?;
+        writer.write_all(script.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// Runs `command` in the session shell in the foreground, see `attach`.
+    pub async fn run(&mut self, command: &str, foreground: &Arc<Foreground>) -> Result<PtyRun, String> {
+        if let Ok(mut captured) = self.captured.lock() {
+            *captured = Capture::new();
+        }
+        self.send(command, true)?;
+        self.attach(foreground, false).await
+    }
+
+    /// Runs `command`, which prints nothing and reads nothing, in the
+    /// session shell for its exit code alone, `None` if the shell exited.
+    pub async fn check(&mut self, command: &str) -> Result<Option<i32>, String> {
+        self.send(command, false)?;
+        let state = next_report(&mut self.session).await;
+        Ok(state.map(|state| state.exit_code))
+    }
+
+    /// Has the session shell exit after the command it runs, which then
+    /// goes on as an ordinary job. Returns whether it was the session
+    /// shell until now.
+    pub fn end_session(&mut self) -> bool {
+        match self.session.as_mut().and_then(|session| session.script.take()) {
+            Some(mut script) => {
+                let _ = script.write_all(session::EXIT.as_bytes());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether this is the session shell, rather than a job.
+    pub fn is_session(&self) -> bool {
+        self.session.as_ref().is_some_and(|session| session.script.is_some())
+    }
+
+    /// Connects the command to Dirac's terminal, continuing it if `resume`
+    /// is set, and waits until it exits or stops, or until the session
+    /// shell reports it done. Keys go to the command in raw mode and window
+    /// size changes are passed on; its process group is `foreground`
+    /// meanwhile.
+    pub async fn attach(&mut self, foreground: &Arc<Foreground>, resume: bool) -> Result<PtyRun, String> {
+        let mut resized = signal(SignalKind::window_change()).map_err(|e| e.to_string())?;
+        let mut children = signal(SignalKind::child()).map_err(|e| e.to_string())?;
+        // The window may have changed while the command ran in the background
+        let _ = self.master.resize(terminal_size());
+
+        foreground.start(self.pid, self.master.as_raw_fd());
+        let raw_mode = RawMode::enable();
+        let stop = Arc::new(AtomicBool::new(false));
+        let input_stop = Arc::clone(&stop);
+        let input_foreground = Arc::clone(foreground);
+        let writer = Arc::clone(&self.writer);
+        let master_fd = self.master.as_raw_fd();
+        let input = thread::spawn(move || forward_input(writer, master_fd, input_foreground, input_stop));
+        if resume {
+            self.resume();
+        }
+
+        let mut reporting = self.session.as_ref().is_some_and(|session| session.script.is_some());
+        let (status, state) = loop {
+            if let Some(status) = self.try_wait() {
+                break (status, None);
+            }
+            tokio::select! {
+                _ = children.recv() => {}
+                _ = resized.recv() => {
+                    let _ = self.master.resize(terminal_size());
+                }
+                report = next_report(&mut self.session), if reporting => match report {
+                    Some(state) => break (PtyStatus::Exited { exit_code: Some(state.exit_code), signal: None }, Some(state)),
+                    // The shell is gone, which SIGCHLD tells next
+                    None => reporting = false,
+                },
+            }
+        };
+
+        let interrupted = foreground.finish();
+        stop.store(true, Ordering::SeqCst);
+        let _ = input.join();
+        match (&status, &state) {
+            (PtyStatus::Exited { .. }, Some(_)) => {
+                if let Some(session) = &mut self.session {
+                    let _ = timeout(DRAIN_TIMEOUT, session.fenced.recv()).await;
+                }
+            }
+            (PtyStatus::Exited { .. }, None) => {
+                if let Some(drained) = self.drained.take() {
+                    let _ = timeout(DRAIN_TIMEOUT, drained).await;
+                }
+            }
+            (PtyStatus::Stopped, _) => {}
+        }
+        drop(raw_mode);
+
+        let last_byte = self.captured.lock().ok().and_then(|captured| captured.last_byte());
+        // The prompt starts on a line of its own, as after any other command
+        if last_byte.is_some_and(|last| last != b'\n') {
+            println!();
+        }
+        Ok(PtyRun { status, interrupted, output: self.output(), state })
+    }
+}
@@ -0,0 +1,118 @@
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, timeout};
+
+/// Longest pause between two attempts, whatever the backoff or the server asks for.
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Timeouts and retries for the requests of one backend.
+#[derive(Debug, Clone)]
+pub struct RequestPolicy {
+    /// How long establishing a connection may take
+    pub connect_timeout: Duration,
+    /// How long the server may take to start answering, which includes
+    /// loading the model into memory on first use
+    pub load_timeout: Duration,
+    /// How long a streamed answer may stall between two chunks
+    pub read_timeout: Duration,
+    /// Attempts after the first one for connect timeouts, dropped
+    /// connections and `429`/`502`/`503`/`504` answers
+    pub max_retries: u32,
+    /// Pause before the first retry, doubled for each further one
+    pub initial_backoff: Duration,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            load_timeout: Duration::from_secs(300),
+            read_timeout: Duration::from_secs(60),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Why a request failed after all attempts.
+#[derive(Debug)]
+pub enum SendFailure {
+    /// The request could not be sent, e.g. nothing listens on the port
+    Request(reqwest::Error),
+    /// No answer started within `load_timeout`; not retried
+    TimedOut(Duration),
+    /// The server kept answering with a retriable status
+    Status { status: StatusCode, body: String },
+    /// The server kept reporting that the model is loading
+    Loading,
+}
+
+fn is_retriable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// The pause a `Retry-After: <seconds>` header asks for.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
+
+impl RequestPolicy {
+    /// A client connecting within `connect_timeout`. The other limits are
+    /// applied per request, as a total timeout would cut long answers short.
+    pub fn client(&self) -> Client {
+        Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .build()
+            .unwrap_or_default()
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF)
+    }
+
+    /// Sends the request built by `build`, retrying transient failures with
+    /// exponential backoff. Servers such as llama-server answer `503` while
+    /// the model loads; those are retried for up to `load_timeout` without
+    /// counting against `max_retries`. Any other answer is returned as is.
+    pub async fn send(&self, build: impl Fn() -> RequestBuilder) -> Result<Response, SendFailure> {
+        let started = Instant::now();
+        let mut attempt = 0;
+        let mut loading_polls = 0;
+        loop {
+            let failure = match timeout(self.load_timeout, build().send()).await {
+                Ok(Ok(response)) if is_retriable(response.status()) => {
+                    let status = response.status();
+                    let pause = retry_after(&response);
+                    let body = response.text().await.unwrap_or_default();
+                    if status == StatusCode::SERVICE_UNAVAILABLE && body.to_lowercase().contains("loading") {
+                        if started.elapsed() >= self.load_timeout {
+                            return Err(SendFailure::Loading);
+                        }
+                        sleep(pause.unwrap_or_else(|| self.backoff(loading_polls)).min(MAX_BACKOFF)).await;
+                        loading_polls += 1;
+                        continue;
+                    }
+                    (SendFailure::Status { status, body }, pause)
+                }
+                Ok(Ok(response)) => return Ok(response),
+                // A refused connection is not going to change within seconds
+                Ok(Err(e)) if e.is_connect() && !e.is_timeout() => return Err(SendFailure::Request(e)),
+                Ok(Err(e)) => (SendFailure::Request(e), None),
+                // Neither is a server that has been silent for the whole load timeout
+                Err(_) => return Err(SendFailure::TimedOut(self.load_timeout)),
+            };
+            let (failure, pause) = failure;
+            if attempt >= self.max_retries {
+                return Err(failure);
+            }
+            sleep(pause.unwrap_or_else(|| self.backoff(attempt)).min(MAX_BACKOFF)).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::core::lib::{AIProcessor, AIResponse, ChatMessage, DiracError, DiracResult, PromptKind};
+use crate::core::suggestion::Suggestion;
+
+/// Tries several backends in priority order, moving on to the next one when
+/// a backend is unreachable, times out or does not have the model.
+pub struct RouterProcessor {
+    providers: Vec<Box<dyn AIProcessor>>,
+}
+
+impl RouterProcessor {
+    pub fn new(providers: Vec<Box<dyn AIProcessor>>) -> Self {
+        Self { providers }
+    }
+
+    /// The error once every provider failed: a missing model as it was
+    /// reported, so the user can be offered to pull it, and otherwise all
+    /// the reasons together.
+    fn all_failed(failures: Vec<String>, missing_model: Option<DiracError>) -> DiracError {
+        if let Some(error) = missing_model {
+            return error;
+        }
+        DiracError::AIUnavailableError(format!(
+            "No AI provider could answer:\n{}",
+            failures.join("\n")
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl AIProcessor for RouterProcessor {
+    fn name(&self) -> String {
+        self.providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    /// Model management applies to the primary provider; the fallbacks keep
+    /// their configured models.
+    fn model(&self) -> Option<String> {
+        self.providers.first()?.model()
+    }
+
+    async fn is_model_loaded(&self) -> Option<bool> {
+        self.providers.first()?.is_model_loaded().await
+    }
+
+    fn set_model(&self, model: &str) -> DiracResult<()> {
+        match self.providers.first() {
+            Some(provider) => provider.set_model(model),
+            None => Err(DiracError::InputError("No AI provider is configured".to_string())),
+        }
+    }
+
+    async fn process<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<Suggestion> {
+        let mut failures = Vec::new();
+        let mut missing_model = None;
+        for provider in &self.providers {
+            match provider.process(kind, input, context, history).await {
+                Err(DiracError::AIUnavailableError(msg)) => {
+                    failures.push(format!("- {}: {}", provider.name(), msg));
+                }
+                Err(e @ DiracError::ModelNotFound(_)) => {
+                    failures.push(format!("- {}: {}", provider.name(), e));
+                    missing_model.get_or_insert(e);
+                }
+                Err(e @ DiracError::ModelLoading(_)) => {
+                    failures.push(format!("- {}: {}", provider.name(), e));
+                }
+                result => return result,
+            }
+        }
+        Err(Self::all_failed(failures, missing_model))
+    }
+
+    async fn process_stream<'a>(&'a self, kind: PromptKind, input: &'a str, context: &'a str, history: &'a [ChatMessage]) -> DiracResult<AIResponse> {
+        // Fail-over only happens while connecting; once tokens have been
+        // shown to the user the answering provider is kept.
+        let mut failures = Vec::new();
+        let mut missing_model = None;
+        for provider in &self.providers {
+            match provider.process_stream(kind, input, context, history).await {
+                Err(DiracError::AIUnavailableError(msg)) => {
+                    failures.push(format!("- {}: {}", provider.name(), msg));
+                }
+                Err(e @ DiracError::ModelNotFound(_)) => {
+                    failures.push(format!("- {}: {}", provider.name(), e));
+                    missing_model.get_or_insert(e);
+                }
+                Err(e @ DiracError::ModelLoading(_)) => {
+                    failures.push(format!("- {}: {}", provider.name(), e));
+                }
+                result => return result,
+            }
+        }
+        Err(Self::all_failed(failures, missing_model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::mock::{ScriptedAIProcessor, ScriptedResponse};
+
+    fn failing(response: ScriptedResponse) -> Box<dyn AIProcessor> {
+        Box::new(ScriptedAIProcessor::new().respond("", response))
+    }
+
+    fn answering(command: &str) -> Box<dyn AIProcessor> {
+        Box::new(ScriptedAIProcessor::new().suggest("", command, ""))
+    }
+
+    #[tokio::test]
+    async fn falls_over_to_the_next_provider() {
+        let router = RouterProcessor::new(vec![
+            failing(ScriptedResponse::Unavailable("offline".to_string())),
+            failing(ScriptedResponse::MissingModel("llama3".to_string())),
+            answering("ls"),
+        ]);
+        let suggestion = router.process(PromptKind::Generate, "list", "", &[]).await.unwrap();
+        assert_eq!(suggestion.command, "ls");
+    }
+
+    #[tokio::test]
+    async fn passes_a_missing_model_through_when_all_fail() {
+        let router = RouterProcessor::new(vec![
+            failing(ScriptedResponse::MissingModel("llama3".to_string())),
+            failing(ScriptedResponse::Unavailable("offline".to_string())),
+        ]);
+        let error = router.process(PromptKind::Generate, "list", "", &[]).await.unwrap_err();
+        assert!(matches!(error, DiracError::ModelNotFound(model) if model == "llama3"));
+        let error = router.process_stream(PromptKind::Generate, "list", "", &[]).await.err().unwrap();
+        assert!(matches!(error, DiracError::ModelNotFound(model) if model == "llama3"));
+    }
+
+    #[tokio::test]
+    async fn reports_every_provider_when_none_is_reachable() {
+        let router = RouterProcessor::new(vec![
+            failing(ScriptedResponse::Unavailable("offline".to_string())),
+            failing(ScriptedResponse::Unavailable("refused".to_string())),
+        ]);
+        let error = router.process(PromptKind::Generate, "list", "", &[]).await.unwrap_err();
+        assert!(matches!(&error, DiracError::AIUnavailableError(msg) if msg.contains("offline") && msg.contains("refused")));
+    }
+}
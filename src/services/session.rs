@@ -0,0 +1,459 @@
+use crate::services::capture::Capture;
+use colored::control::SHOULD_COLORIZE;
+use std::ffi::OsString;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::pipe;
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command as TokioCommand};
+use tokio::time::timeout;
+
+/// Descriptor a session shell reports on after each command.
+const STATUS_FD: RawFd = 3;
+
+/// Sent to a new session shell first: aliases work outside interactive bash
+/// too, and CTRL-C ends the command rather than the shell.
+pub const STARTUP: &str = "shopt -s expand_aliases 2>/dev/null\ntrap : INT QUIT\n";
+
+/// Makes a session shell exit once its command is done, with the exit code
+/// of the command.
+pub const EXIT: &str = "exit \"$__dirac_status\"\n";
+
+/// How long output still in a pipe is waited for once the shell itself has
+/// exited, as background processes it started may hold the pipe open.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Where a session shell was left after a command.
+#[derive(Debug, Clone)]
+pub struct ShellState {
+    pub exit_code: i32,
+    pub cwd: String,
+    /// Exported variables only
+    pub env: Vec<(OsString, OsString)>,
+}
+
+/// A word no command prints, marking the end of its output and of its
+/// status. Unique to each session.
+#[derive(Debug, Clone)]
+pub struct Sentinel {
+    word: String,
+    /// zsh runs only programs through `command`
+    zsh: bool,
+}
+
+impl Sentinel {
+    pub fn new(shell: &str) -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos()).unwrap_or(0);
+        Self { word: format!("__dirac_{:x}_{}__", nanos, std::process::id()), zsh: shell.ends_with("zsh") }
+    }
+
+    /// The lines making a session shell run `command` with `stdin`, then
+    /// print the sentinel after its output, on stderr too if `fence_stderr`
+    /// is set, and report its state on `STATUS_FD`. `eval` reads the
+    /// command, so a syntax error fails it rather than swallowing the lines
+    /// after it, and `command` keeps an error in it from ending the shell.
+    pub fn script(&self, command: &str, stdin: &str, fence_stderr: bool) -> String {
+        let eval = if self.zsh { "eval" } else { "command eval" };
+        let fence = format!("printf '\\n%s\\n' '{}'", self.word);
+        let stderr_fence = if fence_stderr { format!("{} >&2\n", fence) } else { String::new() };
+        format!(
+            "{eval} {command} <{stdin} {fd}>&-\n\
+             __dirac_status=$?\n\
+             {fence}\n\
+             {stderr_fence}\
+             {{ printf '%s\\0' \"$__dirac_status\" \"$PWD\"; env -0; printf '%s\\n' '{word}'; }} >&{fd}\n",
+            eval = eval,
+            command = quote(command),
+            stdin = stdin,
+            fd = STATUS_FD,
+            fence = fence,
+            stderr_fence = stderr_fence,
+            word = self.word,
+        )
+    }
+}
+
+/// Quotes `text` as a single shell word.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// A command exiting with 0 if `name` is an alias or a function of the
+/// shell. POSIX has no way of asking about functions, so dash never has any.
+pub fn definition_check(name: &str) -> String {
+    format!("alias {0} >/dev/null 2>&1 || typeset -f {0} >/dev/null 2>&1", quote(name))
+}
+
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes.windows(needle.len()).position(|window| window == needle)
+}
+
+/// A pipe for `STATUS_FD`: the end Dirac reads and the end the shell gets,
+/// which must be given to it with `pass_status` and then closed.
+pub fn status_pipe(sentinel: &Sentinel) -> io::Result<(StatusReader, OwnedFd)> {
+    let (read, write) = io::pipe()?;
+    let reader = StatusReader {
+        pipe: pipe::Receiver::from_owned_fd(OwnedFd::from(read))?,
+        buffer: Vec::new(),
+        end: format!("\0{}\n", sentinel.word).into_bytes(),
+    };
+    Ok((reader, OwnedFd::from(write)))
+}
+
+/// Runs in the forked child before `exec`, making `status` its
+/// `STATUS_FD`.
+pub fn pass_status(status: RawFd) -> io::Result<()> {
+    let passed = unsafe {
+        if status == STATUS_FD {
+            libc::fcntl(STATUS_FD, libc::F_SETFD, 0)
+        } else {
+            libc::dup2(status, STATUS_FD)
+        }
+    };
+    if passed == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads what a session shell reports on `STATUS_FD`.
+#[derive(Debug)]
+pub struct StatusReader {
+    pipe: pipe::Receiver,
+    buffer: Vec<u8>,
+    end: Vec<u8>,
+}
+
+impl StatusReader {
+    /// The state the shell was left in by the command sent last, `None`
+    /// once it has exited. Nothing is lost if the future is dropped.
+    pub async fn next(&mut self) -> io::Result<Option<ShellState>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(end) = find(&self.buffer, &self.end) {
+                let report: Vec<u8> = self.buffer.drain(..end + self.end.len()).take(end).collect();
+                return parse_state(report)
+                    .map(Some)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the shell sent an unreadable status"));
+            }
+            let read = self.pipe.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// Reads the exit code, the directory and the `NAME=value` environment
+/// entries, each ended by a NUL.
+fn parse_state(report: Vec<u8>) -> Option<ShellState> {
+    let mut fields = report.split(|&byte| byte == 0);
+    let exit_code = String::from_utf8_lossy(fields.next()?).parse().ok()?;
+    let cwd = String::from_utf8_lossy(fields.next()?).to_string();
+    let env = fields
+        .filter_map(|entry| {
+            let separator = entry.iter().position(|&byte| byte == b'=')?;
+            Some((OsString::from_vec(entry[..separator].to_vec()), OsString::from_vec(entry[separator + 1..].to_vec())))
+        })
+        .collect();
+    Some(ShellState { exit_code, cwd, env })
+}
+
+/// Finds the sentinel line after each command's output in a stream,
+/// passing everything else on.
+#[derive(Debug)]
+pub struct Fence {
+    /// The sentinel with the newline put before it
+    marker: Vec<u8>,
+    /// Bytes that may be the start of the marker
+    pending: Vec<u8>,
+    /// Set while the rest of the sentinel line is still to be dropped
+    in_marker_line: bool,
+}
+
+impl Fence {
+    pub fn new(sentinel: &Sentinel) -> Self {
+        Self { marker: format!("\n{}", sentinel.word).into_bytes(), pending: Vec::new(), in_marker_line: false }
+    }
+
+    /// Takes the next bytes of the stream, returning those to pass on and
+    /// whether a sentinel was among them.
+    pub fn push(&mut self, bytes: &[u8]) -> (Vec<u8>, bool) {
+        self.pending.extend_from_slice(bytes);
+        let mut output = Vec::new();
+        let mut found = false;
+        loop {
+            if self.in_marker_line {
+                let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') else {
+                    self.pending.clear();
+                    return (output, found);
+                };
+                self.pending.drain(..=end);
+                self.in_marker_line = false;
+            }
+            let Some(start) = find(&self.pending, &self.marker) else {
+                break;
+            };
+            let mut before: Vec<u8> = self.pending.drain(..start + self.marker.len()).take(start).collect();
+            // A terminal sends the newline as "\r\n"
+            if before.last() == Some(&b'\r') {
+                before.pop();
+            }
+            output.extend(before);
+            found = true;
+            self.in_marker_line = true;
+        }
+
+        let length = self.pending.len();
+        let mut held = (1..self.marker.len().min(length + 1))
+            .rev()
+            .find(|&held| self.marker.starts_with(&self.pending[length - held..]))
+            .unwrap_or(0);
+        // A "\r" may be the start of the "\r\n" before the marker
+        if held < length && self.pending[length - held - 1] == b'\r' {
+            held += 1;
+        }
+        output.extend(self.pending.drain(..length - held));
+        (output, found)
+    }
+}
+
+/// Copies a command's stdout or stderr to Dirac's own up to the sentinel,
+/// stderr in red, and keeps a bounded copy in `capture`.
+async fn stream_output(output: &mut (impl AsyncRead + Unpin), fence: &mut Fence, capture: &mut Capture, is_stderr: bool) {
+    let color = is_stderr && SHOULD_COLORIZE.should_colorize();
+    let mut buffer = [0u8; 4096];
+    while let Ok(read) = output.read(&mut buffer).await {
+        if read == 0 {
+            break;
+        }
+        let (chunk, found) = fence.push(&buffer[..read]);
+        if !chunk.is_empty() {
+            capture.push(&chunk);
+            // Output that cannot be shown is still read up to the sentinel
+            let _ = if is_stderr {
+                let mut stderr = tokio::io::stderr();
+                let colored = if color { [&b"\x1b[31m"[..], &chunk, &b"\x1b[0m"[..]].concat() } else { chunk };
+                stderr.write_all(&colored).await.and(stderr.flush().await)
+            } else {
+                let mut stdout = tokio::io::stdout();
+                stdout.write_all(&chunk).await.and(stdout.flush().await)
+            };
+        }
+        if found {
+            break;
+        }
+    }
+}
+
+/// A shell kept running between commands, so what they change in it lasts:
+/// the directory, exported variables, aliases and functions. Commands get
+/// no terminal here, and their stdout and stderr come through pipes. See
+/// `PtyProcess::spawn_session` for the one with a terminal.
+#[derive(Debug)]
+pub struct ShellSession {
+    child: Child,
+    script: ChildStdin,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    stdout_fence: Fence,
+    stderr_fence: Fence,
+    status: StatusReader,
+    sentinel: Sentinel,
+}
+
+impl ShellSession {
+    /// Starts `shell` in `cwd` with exactly the variables in `env`, in a
+    /// process group of its own.
+    pub async fn spawn(shell: &str, cwd: &str, env: &[(OsString, OsString)]) -> io::Result<Self> {
+        let sentinel = Sentinel::new(shell);
+        let (status, status_fd) = status_pipe(&sentinel)?;
+        let passed_fd = status_fd.as_raw_fd();
+        let mut command = TokioCommand::new(shell);
+        command
+            .env_clear()
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .env("TERM", "xterm-256color")
+            .env("SHELL", shell)
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true);
+        unsafe { command.pre_exec(move || pass_status(passed_fd)) };
+        let mut child = command.spawn()?;
+        // Only the shell may hold it open, or its end goes unnoticed
+        drop(status_fd);
+
+        let missing = || io::Error::other("the shell has no pipes");
+        let mut script = child.stdin.take().ok_or_else(missing)?;
+        let stdout = child.stdout.take().ok_or_else(missing)?;
+        let stderr = child.stderr.take().ok_or_else(missing)?;
+        script.write_all(STARTUP.as_bytes()).await?;
+        Ok(Self {
+            child,
+            script,
+            stdout,
+            stderr,
+            stdout_fence: Fence::new(&sentinel),
+            stderr_fence: Fence::new(&sentinel),
+            status,
+            sentinel,
+        })
+    }
+
+    /// The shell, which leads the process group of the commands it runs.
+    pub fn pid(&self) -> i32 {
+        self.child.id().map_or(0, |pid| pid as i32)
+    }
+
+    pub fn has_exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Runs `command` with no input, showing its output as it comes and
+    /// keeping a copy. Returns the state the shell was left in, `None` if
+    /// the command made it exit.
+    pub async fn run(&mut self, command: &str, stdout: &mut Capture, stderr: &mut Capture) -> io::Result<Option<ShellState>> {
+        self.script.write_all(self.sentinel.script(command, "/dev/null", true).as_bytes()).await?;
+
+        let Self { stdout: command_stdout, stderr: command_stderr, stdout_fence, stderr_fence, status, .. } = self;
+        let streams = async {
+            tokio::join!(
+                stream_output(command_stdout, stdout_fence, stdout, false),
+                stream_output(command_stderr, stderr_fence, stderr, true)
+            )
+        };
+        tokio::pin!(streams);
+        let mut streamed = false;
+        let state = tokio::select! {
+            state = status.next() => state?,
+            _ = &mut streams => {
+                streamed = true;
+                status.next().await?
+            }
+        };
+        if !streamed {
+            match state {
+                // The shell prints the sentinels before it reports
+                Some(_) => {
+                    streams.await;
+                }
+                None => {
+                    let _ = timeout(DRAIN_TIMEOUT, streams).await;
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Runs `command`, which prints nothing, for its exit code alone.
+    pub async fn check(&mut self, command: &str) -> io::Result<Option<i32>> {
+        let (mut stdout, mut stderr) = (Capture::new(), Capture::new());
+        let state = self.run(command, &mut stdout, &mut stderr).await?;
+        Ok(state.map(|state| state.exit_code))
+    }
+
+    /// How the shell ended, once `run` found it gone.
+    pub async fn exit_status(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fence() -> (Fence, String) {
+        let sentinel = Sentinel::new("/bin/sh");
+        (Fence::new(&sentinel), sentinel.word)
+    }
+
+    /// Feeds `pieces` one after another, returning what was passed on and
+    /// whether a sentinel was found.
+    fn feed(fence: &mut Fence, pieces: &[&[u8]]) -> (Vec<u8>, bool) {
+        let mut output = Vec::new();
+        let mut found = false;
+        for piece in pieces {
+            let (passed, seen) = fence.push(piece);
+            output.extend(passed);
+            found |= seen;
+        }
+        (output, found)
+    }
+
+    #[test]
+    fn fence_passes_output_and_drops_the_sentinel_line() {
+        let (mut fence, word) = fence();
+        let (output, found) = fence.push(format!("hello\nworld\n{}\n", word).as_bytes());
+        assert!(found);
+        assert_eq!(output, b"hello\nworld");
+        // The rest of the sentinel line may come in a later read
+        let (output, found) = fence.push(format!("hello\n{}", word).as_bytes());
+        assert!(found);
+        assert_eq!(output, b"hello");
+        let (output, found) = fence.push(b" and the rest\nnext");
+        assert!(!found);
+        assert_eq!(output, b"next");
+    }
+
+    #[test]
+    fn fence_finds_a_sentinel_split_across_reads() {
+        let (mut fence, word) = fence();
+        let stream = format!("output\r\n{}\r\n", word);
+        let bytes = stream.as_bytes();
+        for split in 1..bytes.len() {
+            let (output, found) = feed(&mut fence, &[&bytes[..split], &bytes[split..]]);
+            assert!(found, "split at {}", split);
+            assert_eq!(output, b"output", "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn fence_holds_back_only_what_may_be_the_sentinel() {
+        let (mut fence, word) = fence();
+        let (output, found) = fence.push(b"partial line\n__dir");
+        assert!(!found);
+        assert_eq!(output, b"partial line");
+        // Not the sentinel after all; the newline may start it again
+        let (output, found) = fence.push(b"ty\n");
+        assert!(!found);
+        assert_eq!(output, b"\n__dirty");
+
+        let (output, found) = fence.push(format!("no newline before {}\r", word).as_bytes());
+        assert!(!found);
+        assert_eq!(output, format!("\nno newline before {}", word).as_bytes());
+    }
+
+    #[test]
+    fn parse_state_reads_status_directory_and_environment() {
+        let report = b"3\0/home/bob\0PATH=/usr/bin:/bin\0GREETING=a=b\0\0".to_vec();
+        let state = parse_state(report).unwrap();
+        assert_eq!(state.exit_code, 3);
+        assert_eq!(state.cwd, "/home/bob");
+        assert_eq!(
+            state.env,
+            [
+                (OsString::from("PATH"), OsString::from("/usr/bin:/bin")),
+                (OsString::from("GREETING"), OsString::from("a=b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_state_rejects_a_truncated_report() {
+        assert!(parse_state(Vec::new()).is_none());
+        assert!(parse_state(b"not a status\0/tmp\0".to_vec()).is_none());
+        assert!(parse_state(b"0".to_vec()).is_none());
+    }
+
+    #[test]
+    fn quote_survives_single_quotes() {
+        assert_eq!(quote("echo 'hi' there"), r#"'echo '\''hi'\'' there'"#);
+    }
+}
@@ -0,0 +1,241 @@
+use candle_core::quantized::gguf_file::Value;
+use fancy_regex::Regex;
+use std::collections::HashMap;
+
+/// How GPT-2 splits text into words before merging, for vocabularies
+/// without a more specific pattern.
+const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+/// Llama 3 and most later vocabularies, which group digits in threes.
+const LLAMA3_PATTERN: &str =
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+/// Qwen2, which splits numbers into single digits.
+const QWEN2_PATTERN: &str =
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// GGUF token types of special tokens: control and user defined.
+const SPECIAL_TOKEN_TYPES: [i32; 2] = [3, 4];
+
+/// The printable characters byte-level BPE uses to stand for each byte.
+fn byte_chars() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut shifted = 0;
+    for byte in 0..=255u8 {
+        let printable = matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        chars[byte as usize] = if printable {
+            byte as char
+        } else {
+            shifted += 1;
+            char::from_u32(255 + shifted).unwrap_or('\0')
+        };
+    }
+    chars
+}
+
+fn strings(metadata: &HashMap<String, Value>, key: &str) -> Result<Vec<String>, String> {
+    let values = metadata
+        .get(key)
+        .ok_or_else(|| format!("the file has no {}", key))?
+        .to_vec()
+        .map_err(|e| e.to_string())?;
+    values
+        .iter()
+        .map(|value| value.to_string().cloned().map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// Byte-level BPE tokenizer (GPT-2, Llama 3, Qwen) built from the vocabulary
+/// and merges stored in a GGUF file, so no `tokenizer.json` is needed.
+#[derive(Debug)]
+pub struct Tokenizer {
+    tokens: Vec<String>,
+    ids: HashMap<String, u32>,
+    /// `left right` to the priority of merging the pair, lowest first
+    ranks: HashMap<String, usize>,
+    /// Control tokens, which decode to nothing
+    special: Vec<u32>,
+    pattern: Regex,
+    byte_chars: [char; 256],
+    char_bytes: HashMap<char, u8>,
+}
+
+impl Tokenizer {
+    pub fn from_gguf(metadata: &HashMap<String, Value>) -> Result<Self, String> {
+        let kind = metadata
+            .get("tokenizer.ggml.model")
+            .and_then(|value| value.to_string().ok())
+            .map(String::as_str)
+            .unwrap_or("");
+        if kind != "gpt2" {
+            return Err(format!(
+                "its '{}' tokenizer is not supported, only byte-level BPE vocabularies such as those of Llama 3 and Qwen",
+                kind
+            ));
+        }
+        let tokens = strings(metadata, "tokenizer.ggml.tokens")?;
+        let merges = strings(metadata, "tokenizer.ggml.merges")?;
+        let types: Vec<i32> = metadata
+            .get("tokenizer.ggml.token_type")
+            .and_then(|value| value.to_vec().ok())
+            .map(|types| types.iter().map(|t| t.to_i32().unwrap_or(1)).collect())
+            .unwrap_or_default();
+
+        let pattern = match metadata.get("tokenizer.ggml.pre").and_then(|value| value.to_string().ok()) {
+            Some(pre) if pre == "qwen2" => QWEN2_PATTERN,
+            Some(pre) if pre == "gpt-2" || pre == "default" => GPT2_PATTERN,
+            _ => LLAMA3_PATTERN,
+        };
+        let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+
+        let ids: HashMap<String, u32> = tokens.iter().enumerate().map(|(id, token)| (token.clone(), id as u32)).collect();
+        let ranks = merges.into_iter().enumerate().map(|(rank, merge)| (merge, rank)).collect();
+        let special = types
+            .iter()
+            .enumerate()
+            .filter(|(_, kind)| SPECIAL_TOKEN_TYPES.contains(kind))
+            .map(|(id, _)| id as u32)
+            .collect();
+
+        let byte_chars = byte_chars();
+        let char_bytes = byte_chars.iter().enumerate().map(|(byte, c)| (*c, byte as u8)).collect();
+        Ok(Self {
+            tokens,
+            ids,
+            ranks,
+            special,
+            pattern,
+            byte_chars,
+            char_bytes,
+        })
+    }
+
+    pub fn token_id(&self, token: &str) -> Option<u32> {
+        self.ids.get(token).copied()
+    }
+
+    /// Encodes `text` as plain text: a special token such as `<|im_end|>`
+    /// spelled out in it stays the characters it is spelled with, so text
+    /// from files or command output cannot pose as chat scaffolding.
+    pub fn encode(&self, text: &str) -> Result<Vec<u32>, String> {
+        let mut ids = Vec::new();
+        for word in self.pattern.find_iter(text) {
+            let word = word.map_err(|e| e.to_string())?;
+            self.merge(word.as_str(), &mut ids)?;
+        }
+        Ok(ids)
+    }
+
+    /// Splits a word into tokens by repeatedly merging the adjacent pair
+    /// with the lowest rank.
+    fn merge(&self, word: &str, ids: &mut Vec<u32>) -> Result<(), String> {
+        let spelled: String = word.bytes().map(|byte| self.byte_chars[byte as usize]).collect();
+        if let Some(id) = self.token_id(&spelled) {
+            ids.push(id);
+            return Ok(());
+        }
+        let mut parts: Vec<String> = spelled.chars().map(String::from).collect();
+        loop {
+            let best = parts
+                .windows(2)
+                .enumerate()
+                .filter_map(|(index, pair)| self.ranks.get(&format!("{} {}", pair[0], pair[1])).map(|rank| (*rank, index)))
+                .min();
+            let Some((_, index)) = best else {
+                break;
+            };
+            let merged = format!("{}{}", parts[index], parts[index + 1]);
+            parts.splice(index..=index + 1, [merged]);
+        }
+        for part in parts {
+            ids.push(self.token_id(&part).ok_or_else(|| format!("'{}' is not in the vocabulary", part))?);
+        }
+        Ok(())
+    }
+
+    /// The bytes `id` stands for; special tokens stand for none. A token may
+    /// end in the middle of a multi-byte character.
+    pub fn decode(&self, id: u32) -> Vec<u8> {
+        let Some(token) = self.tokens.get(id as usize) else {
+            return Vec::new();
+        };
+        if self.special.contains(&id) {
+            return Vec::new();
+        }
+        let mut bytes = Vec::with_capacity(token.len());
+        for c in token.chars() {
+            match self.char_bytes.get(&c) {
+                Some(byte) => bytes.push(*byte),
+                None => bytes.extend_from_slice(c.to_string().as_bytes()),
+            }
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Value {
+        Value::Array(values.iter().map(|value| Value::String(value.to_string())).collect())
+    }
+
+    /// Every byte as a token of its own, a few merges and the ChatML and
+    /// Llama 3 control tokens.
+    pub(crate) fn tiny() -> Tokenizer {
+        let bytes: Vec<String> = byte_chars().iter().map(char::to_string).collect();
+        let merged = ["ll", "el", "ell", "he", "Ġw"];
+        let special = ["<|im_start|>", "<|im_end|>", "<|begin_of_text|>", "<|start_header_id|>", "<|end_header_id|>", "<|eot_id|>"];
+        let tokens: Vec<&str> = bytes.iter().map(String::as_str).chain(merged).chain(special).collect();
+        let types = (0..tokens.len()).map(|id| Value::I32(if id < bytes.len() + merged.len() { 1 } else { 3 })).collect();
+        let metadata = HashMap::from([
+            ("tokenizer.ggml.model".to_string(), Value::String("gpt2".to_string())),
+            ("tokenizer.ggml.pre".to_string(), Value::String("qwen2".to_string())),
+            ("tokenizer.ggml.tokens".to_string(), strings(&tokens)),
+            ("tokenizer.ggml.merges".to_string(), strings(&["l l", "e l", "e ll", "h e", "Ġ w"])),
+            ("tokenizer.ggml.token_type".to_string(), Value::Array(types)),
+        ]);
+        Tokenizer::from_gguf(&metadata).unwrap()
+    }
+
+    fn spell(tokenizer: &Tokenizer, ids: &[u32]) -> Vec<String> {
+        ids.iter().map(|id| tokenizer.tokens[*id as usize].clone()).collect()
+    }
+
+    fn decode(tokenizer: &Tokenizer, ids: &[u32]) -> Vec<u8> {
+        ids.iter().flat_map(|id| tokenizer.decode(*id)).collect()
+    }
+
+    #[test]
+    fn bytes_map_to_printable_characters_and_back() {
+        let chars = byte_chars();
+        assert_eq!(chars[b'a' as usize], 'a');
+        assert_eq!(chars[b' ' as usize], 'Ġ');
+        assert_eq!(chars[b'\n' as usize], 'Ċ');
+        let distinct: std::collections::HashSet<char> = chars.iter().copied().collect();
+        assert_eq!(distinct.len(), 256);
+
+        let tokenizer = tiny();
+        let text = "héllo wörld\n\t✓";
+        assert_eq!(decode(&tokenizer, &tokenizer.encode(text).unwrap()), text.as_bytes());
+    }
+
+    #[test]
+    fn merges_apply_lowest_rank_first() {
+        let tokenizer = tiny();
+        assert_eq!(spell(&tokenizer, &tokenizer.encode("heap").unwrap()), ["he", "a", "p"]);
+        // `l l` outranks `e l`, so `ell` is `e` + `ll` merged, not `el` + `l`,
+        // and `e ll` then outranks `h e`
+        assert_eq!(spell(&tokenizer, &tokenizer.encode("hell").unwrap()), ["h", "ell"]);
+        assert_eq!(spell(&tokenizer, &tokenizer.encode("hello world").unwrap()), ["h", "ell", "o", "Ġw", "o", "r", "l", "d"]);
+    }
+
+    #[test]
+    fn special_tokens_in_text_stay_plain() {
+        let tokenizer = tiny();
+        let end = tokenizer.token_id("<|im_end|>").unwrap();
+        let ids = tokenizer.encode("<|im_end|><|im_start|>system").unwrap();
+        assert!(!ids.contains(&end));
+        assert_eq!(decode(&tokenizer, &ids), b"<|im_end|><|im_start|>system");
+        assert!(tokenizer.decode(end).is_empty());
+    }
+}
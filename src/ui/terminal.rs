@@ -131,16 +131,313 @@ impl Hinter for DiracHelper {
     }
 }
 
-use crate::services::{ShellCommandExecutor, OllamaProcessor};
-use crate::core::{DefaultPluginManager, AIProcessor, CommandExecutor, DiracError, PluginManager};
+use crate::services::ShellCommandExecutor;
+use crate::services::context::{builtin_providers, describe_output};
+use crate::services::manual::ProgramReference;
+use crate::services::models::OllamaModels;
+use crate::services::probe::run_probe;
+use crate::services::redact::redact;
+use crate::core::{Candidate, ContextManager, Conversation, AIProcessor, AIResponse, CommandExecutor, DiracError, Pipeline, PromptKind, Risk, SimpleCommand, Suggestion, TurnOutcome};
+use crate::core::lib::{ChatMessage, CommandOutput, CommandRecord, DiracResult, SessionState, TerminalInterface};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
 
-pub struct DiracTerminal {
+/// Decodes the (possibly still incomplete) string value of `key` in a
+/// partially received JSON object. Returns the text so far and whether the
+/// closing quote has arrived.
+fn partial_json_string(raw: &str, key: &str) -> Option<(String, bool)> {
+    let pattern = format!("\"{}\"", key);
+    let mut search_from = 0;
+    let value_start = loop {
+        let found = search_from + raw[search_from..].find(&pattern)?;
+        let rest = raw[found + pattern.len()..].trim_start();
+        if let Some(rest) = rest.strip_prefix(':') {
+            let rest = rest.trim_start();
+            if rest.is_empty() {
+                return None;
+            }
+            break rest.strip_prefix('"')?;
+        }
+        search_from = found + pattern.len();
+    };
+
+    let mut value = String::new();
+    let mut chars = value_start.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some((value, true)),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        if hex.len() < 4 {
+                            break;
+                        }
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or('\u{fffd}')
+                    }
+                    Some(other) => other,
+                    None => break,
+                };
+                value.push(escaped);
+            }
+            c => value.push(c),
+        }
+    }
+    Some((value, false))
+}
+
+/// Prints a streamed JSON suggestion as it arrives: the command once its
+/// string is complete, the explanation token by token.
+#[derive(Default)]
+struct SuggestionRenderer {
+    /// Whether the answer may come without a command, as for `ask`
+    prose: bool,
+    raw: String,
+    command_shown: bool,
+    /// Characters of the explanation printed so far
+    explanation_shown: Option<usize>,
+    explanation_done: bool,
+}
+
+impl SuggestionRenderer {
+    fn push(&mut self, chunk: &str, interface: &dyn TerminalInterface) {
+        self.raw.push_str(chunk);
+
+        if !self.command_shown {
+            if let Some((command, true)) = partial_json_string(&self.raw, "command") {
+                // An empty command means the model is asking for probes first
+                if !command.trim().is_empty() {
+                    interface.display_output(&format!("{} {}", "📎 Command:".blue(), command.trim().yellow()));
+                }
+                self.command_shown = true;
+            }
+        }
+
+        if !self.explanation_done {
+            if let Some((explanation, complete)) = partial_json_string(&self.raw, "explanation") {
+                let shown = self.explanation_shown.get_or_insert_with(|| {
+                    interface.display_partial(&format!("{} ", "💡 Details:".blue()));
+                    0
+                });
+                let pending: String = explanation.chars().skip(*shown).collect();
+                *shown += pending.chars().count();
+                interface.display_partial(&pending);
+                if complete {
+                    interface.display_output("");
+                    self.explanation_done = true;
+                }
+            }
+        }
+    }
+
+    /// Parses the complete answer and prints whatever the stream left out.
+    fn finish(self, interface: &dyn TerminalInterface) -> DiracResult<Suggestion> {
+        if self.explanation_shown.is_some() && !self.explanation_done {
+            interface.display_output("");
+        }
+        let suggestion = if self.prose {
+            Suggestion::parse_answer(&self.raw)?
+        } else {
+            Suggestion::parse(&self.raw)?
+        };
+        if !self.command_shown && !suggestion.command.is_empty() {
+            interface.display_output(&format!("{} {}", "📎 Command:".blue(), suggestion.command.yellow()));
+        }
+        if self.explanation_shown.is_none() && !suggestion.explanation.is_empty() {
+            interface.display_output(&format!("{} {}", "💡 Details:".blue(), suggestion.explanation));
+        }
+        Ok(suggestion)
+    }
+}
+
+/// The last `max_chars` characters of `text`, where errors usually are.
+fn tail(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().skip(count - max_chars).collect();
+    format!("[...]{}", kept)
+}
+
+/// At most `max_chars` characters of `text`, keeping its beginning, where
+/// headers are, and its end, where summaries and errors are.
+fn clip(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text.to_string();
+    }
+    let half = max_chars / 2;
+    let head: String = text.chars().take(half).collect();
+    let end: String = text.chars().skip(count - half).collect();
+    format!("{}\n[... {} characters omitted ...]\n{}", head, count - 2 * half, end)
+}
+
+/// Shows the first lines of a probe result under the probe.
+fn display_probe_result(result: &str, interface: &dyn TerminalInterface) {
+    const SHOWN_LINES: usize = 3;
+    let lines: Vec<&str> = result.lines().collect();
+    for line in lines.iter().take(SHOWN_LINES) {
+        interface.display_output(&format!("   {}", line.dimmed()));
+    }
+    if lines.len() > SHOWN_LINES {
+        interface.display_output(&format!("   {}", format!("... ({} more lines)", lines.len() - SHOWN_LINES).dimmed()));
+    }
+}
+
+/// What the user picked from a list of candidate commands.
+enum CandidateChoice {
+    Run(usize),
+    Edit(usize),
+    Cancel,
+}
+
+/// Accepts `y`/`e` for the top candidate, or `<n>`/`e<n>` for any of them.
+fn parse_candidate_choice(answer: &str, count: usize) -> CandidateChoice {
+    let answer = answer.trim().to_lowercase();
+    let (edit, selection) = match answer.strip_prefix('e') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, answer.as_str()),
+    };
+    let index = match selection {
+        "" if edit => Some(0),
+        "y" if !edit => Some(0),
+        number => number.parse::<usize>().ok().filter(|n| (1..=count).contains(n)).map(|n| n - 1),
+    };
+    match index {
+        Some(index) if edit => CandidateChoice::Edit(index),
+        Some(index) => CandidateChoice::Run(index),
+        None => CandidateChoice::Cancel,
+    }
+}
+
+fn colored_risk(risk: Risk) -> ColoredString {
+    match risk {
+        Risk::Low => risk.to_string().green(),
+        Risk::Medium => risk.to_string().yellow(),
+        Risk::High => risk.to_string().red().bold(),
+    }
+}
+
+/// Lets CTRL-C abort the AI request being awaited instead of the whole line
+/// of input. The signal handler cancels while a request is armed and falls
+/// back to the usual banner otherwise.
+#[derive(Debug, Default)]
+struct Cancellation {
+    /// Number of awaits currently armed, nested ones included
+    armed: AtomicUsize,
+    notify: Notify,
+}
+
+/// Disarms its `Cancellation` when dropped.
+struct Armed<'a>(&'a Cancellation);
+
+impl Drop for Armed<'_> {
+    fn drop(&mut self) {
+        self.0.armed.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Cancellation {
+    fn arm(&self) -> Armed<'_> {
+        self.armed.fetch_add(1, Ordering::SeqCst);
+        Armed(self)
+    }
+
+    /// Cancels the armed request, if any. Returns whether there was one.
+    fn cancel(&self) -> bool {
+        if self.armed.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+        self.notify.notify_waiters();
+        true
+    }
+
+    /// Awaits `future` unless CTRL-C comes first. Dropping the request
+    /// closes its connection, which makes Ollama stop generating.
+    async fn race<T>(&self, future: impl Future<Output = DiracResult<T>>) -> DiracResult<T> {
+        // Listen before arming, so a signal in between is not lost
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        let _armed = self.arm();
+        tokio::select! {
+            result = future => result,
+            _ = notified => Err(DiracError::Interrupted),
+        }
+    }
+}
+
+/// e.g. `1.9 GB`, in powers of 1000 like Ollama itself.
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// e.g. `[##########----------]  50%`.
+fn progress_bar(completed: u64, total: u64) -> String {
+    const WIDTH: usize = 30;
+    let fraction = if total == 0 { 0.0 } else { (completed as f64 / total as f64).min(1.0) };
+    let filled = (fraction * WIDTH as f64).round() as usize;
+    format!("[{}{}] {:>3.0}%", "#".repeat(filled), "-".repeat(WIDTH - filled), fraction * 100.0)
+}
+
+/// Commands run by the shell itself, which have no program to look up.
+const SHELL_BUILTINS: &[&str] = &[
+    "cd", "jobs", "fg", "bg", "export", "unset", "source", ".", "alias", "unalias", "set", "exit", "read", "eval", "exec", "type", "umask", "ulimit",
+];
+
+/// Widest token column of an explanation before annotations wrap under it.
+const MAX_TOKEN_WIDTH: usize = 24;
+
+/// Most commands remembered for the AI context.
+const MAX_RECENT_COMMANDS: usize = 50;
+
+/// Most characters of each of stdout and stderr kept from the last command.
+const MAX_CAPTURED_OUTPUT_CHARS: usize = 6000;
+
+/// Most characters of a failed step's output sent back for a revised plan.
+const MAX_FAILURE_CHARS: usize = 1500;
+
+/// Fixes offered in a row for a command that keeps failing.
+const MAX_FIX_ATTEMPTS: usize = 3;
+
+/// Rounds of read-only probes the model may run before it has to answer.
+pub const DEFAULT_PROBE_ROUNDS: usize = 3;
+
+/// How long the first token may take before Dirac asks the backend whether
+/// the model is still being loaded, the usual reason for a slow start.
+const SLOW_START: Duration = Duration::from_secs(2);
+
+/// Line editing backed by rustyline, with Dirac's completion and hints.
+pub struct EditorInterface {
     editor: Editor<DiracHelper, DefaultHistory>,
-    command_executor: ShellCommandExecutor,
-    ai_processor: OllamaProcessor,
 }
 
-impl DiracTerminal {
+impl EditorInterface {
     pub fn new() -> Self {
         let config = Config::builder()
             .completion_type(CompletionType::List)
@@ -150,45 +447,456 @@ impl DiracTerminal {
         let mut editor = Editor::with_config(config).unwrap();
         editor.set_helper(Some(DiracHelper::new()));
 
+        Self { editor }
+    }
+}
+
+impl Default for EditorInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn input_error(error: ReadlineError) -> DiracError {
+    match error {
+        ReadlineError::Interrupted => DiracError::Interrupted,
+        ReadlineError::Eof => DiracError::EndOfInput,
+        other => DiracError::InputError(other.to_string()),
+    }
+}
+
+impl TerminalInterface for EditorInterface {
+    fn read_line(&mut self, prompt: &str) -> DiracResult<String> {
+        self.editor.readline(prompt).map_err(input_error)
+    }
+
+    fn read_line_with_initial(&mut self, prompt: &str, initial: &str) -> DiracResult<String> {
+        self.editor.readline_with_initial(prompt, (initial, "")).map_err(input_error)
+    }
+
+    fn add_history(&mut self, line: &str) {
+        // Update command history in the helper
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.completer.update_history(line.to_string());
+        }
+        self.editor.add_history_entry(line).unwrap_or_default();
+    }
+
+    fn display_output(&self, output: &str) {
+        println!("{}", output);
+        // Ensure output is flushed
+        std::io::stdout().flush().unwrap_or_default();
+    }
+
+    fn display_partial(&self, output: &str) {
+        print!("{}", output);
+        std::io::stdout().flush().unwrap_or_default();
+    }
+
+    // Errors come colored by the caller, some red, some as yellow hints
+    fn display_error(&self, error: &str) {
+        eprintln!("{}", error);
+        std::io::stderr().flush().unwrap_or_default();
+    }
+}
+
+pub struct DiracTerminal {
+    interface: Box<dyn TerminalInterface>,
+    command_executor: ShellCommandExecutor,
+    ai_processor: Box<dyn AIProcessor>,
+    conversation: Conversation,
+    context: ContextManager,
+    recent_commands: Vec<CommandRecord>,
+    /// What the last command printed, for `ask` and the `output` context
+    last_output: Option<CommandOutput>,
+    max_probe_rounds: usize,
+    /// Model management, available with an Ollama backend
+    models: Option<OllamaModels>,
+    cancellation: Arc<Cancellation>,
+}
+
+impl DiracTerminal {
+    pub fn new(ai_processor: Box<dyn AIProcessor>) -> Self {
+        Self::with_interface(ai_processor, Box::new(EditorInterface::new()))
+    }
+
+    /// Creates a terminal reading its input from `interface`, e.g. a scripted
+    /// one in tests.
+    pub fn with_interface(ai_processor: Box<dyn AIProcessor>, interface: Box<dyn TerminalInterface>) -> Self {
+        let mut context = ContextManager::new();
+        for provider in builtin_providers() {
+            context.register(provider);
+        }
+
         Self {
-            editor,
+            interface,
             command_executor: ShellCommandExecutor::new(),
-            ai_processor: OllamaProcessor::with_default_config(),
+            ai_processor,
+            conversation: Conversation::default(),
+            context,
+            recent_commands: Vec::new(),
+            last_output: None,
+            max_probe_rounds: DEFAULT_PROBE_ROUNDS,
+            models: None,
+            cancellation: Arc::default(),
+        }
+    }
+
+    /// Enables the `models` and `model` builtins for the Ollama server
+    /// behind `models`.
+    pub fn set_model_manager(&mut self, models: OllamaModels) {
+        self.models = Some(models);
+    }
+
+    /// Limits how often the model may inspect the system before answering;
+    /// zero disables probes.
+    pub fn set_max_probe_rounds(&mut self, rounds: usize) {
+        self.max_probe_rounds = rounds;
+    }
+
+    /// The providers assembling the context of AI requests, to enable,
+    /// disable or budget them.
+    pub fn context_mut(&mut self) -> &mut ContextManager {
+        &mut self.context
+    }
+
+    fn model_manager(&self) -> Option<&OllamaModels> {
+        if self.models.is_none() {
+            self.interface.display_error(&"Model management is only available with the Ollama backend.".red().to_string());
+        }
+        self.models.as_ref()
+    }
+
+    /// Handles `model`, `model show <name>`, `model pull <name>` and
+    /// `model use <name>`.
+    async fn handle_model_command(&mut self, args: &str) {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        match args.as_slice() {
+            [] => match self.ai_processor.model() {
+                Some(model) => self.interface.display_output(&format!("{} {}", "Active model:".blue(), model.yellow())),
+                None => self.interface.display_output(&format!("{} {}", "Active backend:".blue(), self.ai_processor.name().yellow())),
+            },
+            ["show", name] => self.show_model(name).await,
+            ["pull", name] => {
+                self.pull_model(name).await;
+            }
+            ["use", name] => self.use_model(name).await,
+            _ => self.interface.display_error(&"Usage: model [show|pull|use <name>]".red().to_string()),
+        }
+    }
+
+    async fn list_models(&self) {
+        let Some(models) = self.model_manager() else {
+            return;
+        };
+        let installed = match models.list().await {
+            Ok(installed) => installed,
+            Err(e) => {
+                self.handle_ai_error(e);
+                return;
+            }
+        };
+        if installed.is_empty() {
+            self.interface.display_output(&"No models are installed. Download one with 'model pull <name>'.".yellow().to_string());
+            return;
+        }
+        let active = self.ai_processor.model().unwrap_or_default();
+        let width = installed.iter().map(|model| model.name.len()).max().unwrap_or(0);
+        self.interface.display_output(&"=== Installed Models =====".green().bold().to_string());
+        for model in &installed {
+            let marker = if model.name == active || model.name == format!("{}:latest", active) { "*" } else { " " };
+            self.interface.display_output(&format!(
+                "{} {:width$}  {:>9}  {:>6}  {}",
+                marker.green().bold(),
+                model.name.yellow(),
+                format_size(model.size),
+                model.details.parameter_size,
+                model.details.quantization_level.dimmed(),
+                width = width
+            ));
+        }
+    }
+
+    async fn show_model(&self, name: &str) {
+        let Some(models) = self.model_manager() else {
+            return;
+        };
+        let info = match models.show(name).await {
+            Ok(info) => info,
+            Err(e) => {
+                self.handle_ai_error(e);
+                return;
+            }
+        };
+        self.interface.display_output(&format!("=== {} =====", name).green().bold().to_string());
+        let details = &info.details;
+        for (label, value) in [
+            ("Family", details.family.clone()),
+            ("Parameters", details.parameter_size.clone()),
+            ("Quantization", details.quantization_level.clone()),
+            ("Format", details.format.clone()),
+            ("Context length", info.context_length().map(|n| n.to_string()).unwrap_or_default()),
+        ] {
+            if !value.is_empty() {
+                self.interface.display_output(&format!("  {} {}", format!("{}:", label).blue(), value));
+            }
+        }
+        if !info.parameters.trim().is_empty() {
+            self.interface.display_output(&format!("  {}", "Defaults:".blue()));
+            for line in info.parameters.lines() {
+                self.interface.display_output(&format!("    {}", line.split_whitespace().collect::<Vec<_>>().join(" ")));
+            }
+        }
+        if let Some(license) = info.license.lines().find(|line| !line.trim().is_empty()) {
+            self.interface.display_output(&format!("  {} {}", "License:".blue(), license.trim()));
+        }
+    }
+
+    /// Downloads a model, drawing a progress bar for each layer. Returns
+    /// whether the download completed.
+    async fn pull_model(&self, name: &str) -> bool {
+        let Some(models) = self.model_manager() else {
+            return false;
+        };
+        self.interface.display_output(&format!("{} {}", "Pulling".blue(), name.yellow()));
+        let progress = match self.cancellation.race(models.pull(name)).await {
+            Ok(progress) => progress,
+            Err(DiracError::Interrupted) => {
+                self.interface.display_output(&"⏹  Download cancelled.".yellow().to_string());
+                return false;
+            }
+            Err(e) => {
+                self.handle_ai_error(e);
+                return false;
+            }
+        };
+        futures_util::pin_mut!(progress);
+
+        // Layers get a bar that is redrawn in place; other steps get a line
+        let mut drawing_bar = false;
+        loop {
+            let update = match self.cancellation.race(async { progress.next().await.transpose() }).await {
+                Ok(Some(update)) => update,
+                Ok(None) => break,
+                Err(e) => {
+                    if drawing_bar {
+                        self.interface.display_output("");
+                    }
+                    match e {
+                        DiracError::Interrupted => {
+                            self.interface.display_output(&"⏹  Download cancelled; pulling again resumes it.".yellow().to_string())
+                        }
+                        e => self.handle_ai_error(e),
+                    }
+                    return false;
+                }
+            };
+            match (update.total, update.completed) {
+                (Some(total), completed) if total > 0 => {
+                    let completed = completed.unwrap_or(0);
+                    let layer = update.digest.as_deref().map(|digest| digest.trim_start_matches("sha256:")).unwrap_or("");
+                    self.interface.display_partial(&format!(
+                        "\r\x1B[2K  {} {} {} / {}",
+                        &layer[..layer.len().min(12)],
+                        progress_bar(completed, total),
+                        format_size(completed),
+                        format_size(total)
+                    ));
+                    drawing_bar = true;
+                }
+                _ => {
+                    if drawing_bar {
+                        self.interface.display_output("");
+                        drawing_bar = false;
+                    }
+                    self.interface.display_output(&format!("  {}", update.status.dimmed()));
+                }
+            }
+            if update.status == "success" {
+                self.interface.display_output(&format!("✅ Pulled {}.", name).green().bold().to_string());
+                return true;
+            }
+        }
+        if drawing_bar {
+            self.interface.display_output("");
+        }
+        self.interface.display_error(&format!("❌ The download of {} stopped before it completed.", name).red().to_string());
+        false
+    }
+
+    /// Asks whether to download a missing model. Returns whether it is now installed.
+    async fn offer_pull(&mut self, name: &str) -> bool {
+        if self.models.is_none() {
+            return false;
+        }
+        self.interface.display_output(&format!("Model '{}' is not installed. Pull it now? [y/N]:", name).yellow().to_string());
+        match self.interface.read_line("") {
+            Ok(answer) if answer.trim().eq_ignore_ascii_case("y") => self.pull_model(name).await,
+            _ => false,
+        }
+    }
+
+    async fn use_model(&mut self, name: &str) {
+        let Some(models) = self.model_manager() else {
+            return;
+        };
+        match models.is_installed(name).await {
+            Ok(true) => {}
+            Ok(false) => {
+                if !self.offer_pull(name).await {
+                    self.interface.display_output(&"Model unchanged.".yellow().to_string());
+                    return;
+                }
+            }
+            Err(e) => {
+                self.handle_ai_error(e);
+                return;
+            }
+        }
+        match self.ai_processor.set_model(name) {
+            Ok(()) => self.interface.display_output(&format!("{} {}", "Now using".green(), name.yellow().bold())),
+            Err(e) => self.interface.display_error(&e.to_string().red().to_string()),
+        }
+    }
+
+    /// Offers to pull the configured model at startup when the server is up
+    /// but does not have it. An unreachable server is reported by the first request.
+    async fn check_model(&mut self) {
+        let (Some(models), Some(model)) = (&self.models, self.ai_processor.model()) else {
+            return;
+        };
+        if let Ok(false) = models.is_installed(&model).await {
+            self.offer_pull(&model).await;
+        }
+    }
+
+    /// Reports a failed AI request, offering to download the model when
+    /// that is what was missing.
+    async fn handle_request_error(&mut self, error: DiracError) {
+        match error {
+            DiracError::ModelNotFound(model) if self.models.is_some() => {
+                if self.offer_pull(&model).await {
+                    self.interface.display_output(&"Try your request again.".yellow().to_string());
+                }
+            }
+            error => self.handle_ai_error(error),
+        }
+    }
+
+    /// Environment details for the next AI request.
+    async fn prompt_context(&self) -> String {
+        self.session_context(self.last_output.as_ref()).await
+    }
+
+    /// Environment details, with the output of the last command only when
+    /// `last_output` is given.
+    async fn session_context(&self, last_output: Option<&CommandOutput>) -> String {
+        let cwd = std::path::PathBuf::from(self.command_executor.get_current_dir());
+        let env = self.command_executor.env();
+        self.context.assemble(&SessionState {
+            cwd: &cwd,
+            recent_commands: &self.recent_commands,
+            last_output,
+            env: &env,
+        })
+        .await
+    }
+
+    fn record_command(&mut self, command: &str) {
+        if self.recent_commands.len() == MAX_RECENT_COMMANDS {
+            self.recent_commands.remove(0);
+        }
+        self.recent_commands.push(CommandRecord {
+            command: command.to_string(),
+            exit_code: self.command_executor.last_exit_code(),
+        });
+        // Redacted before clipping, so no secret is cut beyond recognition
+        let capture = |output: String| clip(&redact(&output), MAX_CAPTURED_OUTPUT_CHARS);
+        self.last_output = Some(CommandOutput {
+            command: command.to_string(),
+            exit_code: self.command_executor.last_exit_code(),
+            stdout: capture(self.command_executor.last_stdout()),
+            stderr: capture(self.command_executor.last_stderr()),
+        });
+    }
+
+    /// Handles the `context` builtin.
+    async fn handle_context_command(&mut self, args: &str) {
+        let mut words = args.split_whitespace();
+        let toggle = match (words.next(), words.next()) {
+            (None, _) => None,
+            (Some("on"), Some(name)) => Some((name, true)),
+            (Some("off"), Some(name)) => Some((name, false)),
+            _ => {
+                self.interface.display_error(&"Usage: context [on|off <provider>]".red().to_string());
+                return;
+            }
+        };
+        if let Some((name, enabled)) = toggle {
+            match self.context.set_enabled(name, enabled) {
+                Ok(()) => self.interface.display_output(&format!("Context provider '{}' turned {}.", name, if enabled { "on" } else { "off" }).green().to_string()),
+                Err(e) => self.interface.display_error(&e.to_string().red().to_string()),
+            }
+            return;
+        }
+
+        self.interface.display_output(&"=== Context Providers ===".green().bold().to_string());
+        for info in self.context.list() {
+            let state = if info.enabled { "on".green() } else { "off".red() };
+            self.interface.display_output(&format!("  {} [{}, {} chars] - {}", info.name.yellow(), state, info.budget, info.description));
+        }
+        let context = self.prompt_context().await;
+        self.interface.display_output(&"\n=== Context Sent With Requests ===".green().bold().to_string());
+        if context.is_empty() {
+            self.interface.display_output(&"(empty)".dimmed().to_string());
+        } else {
+            self.interface.display_output(&context);
         }
     }
     
     pub fn display_welcome(&self) {
-        println!("{}", "=== Welcome to Dirac - Your AI-powered terminal! ===".green().bold());
-        println!("{}", "Available features:".blue());
-        println!("{}", " - Natural language command processing".blue());
-        println!("{}", " - Smart command completion and suggestions".blue());
-        println!("{}", " - File path completion".blue());
-        println!("{}", " - Command history with search".blue());
-        println!("{}", " - Plugin system for extended functionality".blue());
-        println!("{}", "\nType 'help' for more information or start typing your commands.".yellow());
+        self.interface.display_output(&"=== Welcome to Dirac - Your AI-powered terminal! ===".green().bold().to_string());
+        self.interface.display_output(&"Available features:".blue().to_string());
+        self.interface.display_output(&" - Natural language command processing".blue().to_string());
+        self.interface.display_output(&" - Smart command completion and suggestions".blue().to_string());
+        self.interface.display_output(&" - File path completion".blue().to_string());
+        self.interface.display_output(&" - Command history with search".blue().to_string());
+        self.interface.display_output(&" - Plugin system for extended functionality".blue().to_string());
+        self.interface.display_output(&"\nType 'help' for more information or start typing your commands.".yellow().to_string());
     }
 
     pub async fn run(&mut self) {
         self.display_welcome();
+        self.check_model().await;
 
         // Set up signal handlers for terminal control
         let (tx, mut rx) = tokio::sync::mpsc::channel(1);
         let tx_clone = tx.clone();
+        let cancellation = Arc::clone(&self.cancellation);
+        let foreground = self.command_executor.foreground();
 
         // Spawn a task to handle terminal control signals
         tokio::spawn(async move {
             use tokio::signal::unix::{signal, SignalKind};
             let mut sigint = signal(SignalKind::interrupt()).unwrap();
+            let mut sigquit = signal(SignalKind::quit()).unwrap();
             let mut sigtstp = signal(SignalKind::from_raw(libc::SIGTSTP)).unwrap();
             let mut sigcont = signal(SignalKind::from_raw(libc::SIGCONT)).unwrap();
 
             loop {
                 tokio::select! {
                     _ = sigint.recv() => {
-                        let _ = tx_clone.send("INT").await;
+                        if !cancellation.cancel() && !foreground.forward(libc::SIGINT) {
+                            let _ = tx_clone.send("INT").await;
+                        }
+                    }
+                    _ = sigquit.recv() => {
+                        foreground.forward(libc::SIGQUIT);
                     }
                     _ = sigtstp.recv() => {
-                        let _ = tx_clone.send("TSTP").await;
+                        if !foreground.forward(libc::SIGTSTP) {
+                            let _ = tx_clone.send("TSTP").await;
+                        }
                     }
                     _ = sigcont.recv() => {
                         let _ = tx_clone.send("CONT").await;
@@ -202,16 +910,16 @@ impl DiracTerminal {
                 signal = rx.recv() => {
                     match signal.unwrap_or_default() {
                         "INT" => {
-                            println!("{}", "\nCTRL-C pressed. Use 'exit' or 'quit' to exit properly.".yellow());
+                            self.interface.display_output(&"\nCTRL-C pressed. Use 'exit' or 'quit' to exit properly.".yellow().to_string());
                             continue;
                         }
                         "TSTP" => {
-                            println!("{}", "\nCTRL-Z pressed. Terminal will continue running.".yellow());
+                            self.interface.display_output(&"\nCTRL-Z pressed. It suspends running commands, not Dirac itself.".yellow().to_string());
                             continue;
                         }
                         "CONT" => {
-                            println!("{}", "\nTerminal resumed.".green());
-                            self.editor.clear_screen().unwrap_or_default();
+                            self.interface.display_output(&"\nTerminal resumed.".green().to_string());
+                            print!("\x1B[2J\x1B[1;1H");
                             self.display_welcome();
                         }
                         _ => {}
@@ -221,20 +929,20 @@ impl DiracTerminal {
                     match input_result {
                         Ok(should_exit) => {
                             if should_exit {
-                                println!("{}", "Goodbye!".green());
+                                self.interface.display_output(&"Goodbye!".green().to_string());
                                 break;
                             }
                         }
-                        Err(ReadlineError::Interrupted) => {
-                            println!("{}", "CTRL-C pressed. Use 'exit' or 'quit' to exit properly.".yellow());
+                        Err(DiracError::Interrupted) => {
+                            self.interface.display_output(&"CTRL-C pressed. Use 'exit' or 'quit' to exit properly.".yellow().to_string());
                             continue;
                         }
-                        Err(ReadlineError::Eof) => {
-                            println!("{}", "CTRL-D pressed. Use 'exit' or 'quit' to exit properly.".yellow());
+                        Err(DiracError::EndOfInput) => {
+                            self.interface.display_output(&"CTRL-D pressed. Use 'exit' or 'quit' to exit properly.".yellow().to_string());
                             continue;
                         }
                         Err(err) => {
-                            eprintln!("{} {}", "Error:".red(), err);
+                            self.interface.display_error(&format!("{} {}", "Error:".red(), err));
                             break;
                         }
                     }
@@ -243,7 +951,13 @@ impl DiracTerminal {
         }
     }
 
-    async fn process_input(&mut self) -> Result<bool, ReadlineError> {
+    async fn process_input(&mut self) -> DiracResult<bool> {
+        for notice in self.command_executor.job_notices() {
+            self.interface.display_output(&notice);
+        }
+        if let Some(notice) = self.command_executor.session_notice() {
+            self.interface.display_output(&notice.yellow().to_string());
+        }
         let current_dir = self.command_executor.get_current_dir();
         let path_components: Vec<&str> = current_dir.split('/').filter(|s| !s.is_empty()).collect();
         let dir_display = if path_components.len() >= 2 {
@@ -254,14 +968,8 @@ impl DiracTerminal {
             "/".to_string()
         };
         let prompt = format!("dirac[{}]> ", dir_display);
-        let line = self.editor.readline(&prompt)?;
-
-        // Update command history in the helper
-        if let Some(helper) = self.editor.helper_mut() {
-            helper.completer.update_history(line.clone());
-        }
-
-        self.editor.add_history_entry(line.as_str()).unwrap();
+        let line = self.interface.read_line(&prompt)?;
+        self.interface.add_history(&line);
         let input = line.trim();
 
         if input.is_empty() {
@@ -272,34 +980,13 @@ impl DiracTerminal {
             return Ok(true);
         }
 
-        match self.command_executor.execute(input).await {
-            Ok(output) => {
-                if !output.is_empty() {
-                    println!("{}", output);
-                }
-            }
-            Err(e) => {
-                eprintln!("{}", e.to_string().red());
-                // Get AI feedback for the failed command
-                match self.ai_processor.process(
-                    &format!("Command '{}' failed. Please explain what went wrong and suggest a solution.", input),
-                    &e.to_string()
-                ).await {
-                    Ok(feedback) => {
-                        println!("");
-                        println!("{}", "🤖 AI Feedback:".blue().bold());
-                        println!("{}", feedback);
-                    }
-                    Err(ai_err) => {
-                        eprintln!("{}", format!("Failed to get AI feedback: {}", ai_err).red());
-                    }
-                }
-            }
-        };
+        self.process_command(input).await;
         Ok(false)
     }
 
-    async fn process_command(&mut self, input: &str) {
+    /// Handles one line of input: a builtin, a shell command or a natural
+    /// language request for the AI.
+    pub async fn process_command(&mut self, input: &str) {
         let input = input.trim();
 
         // Handle empty input
@@ -307,125 +994,729 @@ impl DiracTerminal {
             return;
         }
 
-        // Check for common typos in directory names
-        if input.starts_with("cd ") {
-            let path = input.trim_start_matches("cd ").trim();
-            if !std::path::Path::new(path).exists() {
-                // Try to find similar directory names
-                if let Ok(entries) = std::fs::read_dir(".") {
-                    let similar: Vec<String> = entries
-                        .filter_map(|e| e.ok())
-                        .filter(|e| e.path().is_dir())
-                        .filter_map(|e| e.file_name().into_string().ok())
-                        .filter(|name| name.len() >= 2 && path.len() >= 2 && 
-                                name.chars().next() == path.chars().next())
-                        .collect();
-
-                    if !similar.is_empty() {
-                        println!("{}", "Did you mean one of these directories?".yellow());
-                        for dir in similar {
-                            println!("  {}", dir.blue());
-                        }
-                        return;
-                    }
-                }
-            }
+        if input == "plan" {
+            self.interface.display_error(&"Usage: plan <request>".red().to_string());
+            return;
+        }
+        if let Some(request) = input.strip_prefix("plan ") {
+            self.process_plan_command(request.trim()).await;
+            return;
+        }
+
+        if input == "explain" || input == "?" {
+            self.interface.display_error(&"Usage: explain <command> or ?<command>".red().to_string());
+            return;
+        }
+        if let Some(command) = input.strip_prefix("explain ").or_else(|| input.strip_prefix('?')) {
+            self.process_explain_command(command.trim()).await;
+            return;
+        }
+
+        if input == "ask" {
+            self.interface.display_error(&"Usage: ask <question about the last command's output>".red().to_string());
+            return;
+        }
+        if let Some(question) = input.strip_prefix("ask ") {
+            self.process_ask_command(question.trim()).await;
+            return;
         }
 
-        // Check if it's a natural language navigation command
-        if input.starts_with("go to ") || input.starts_with("open ") || input.starts_with("change to ") {
-            let path = input.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
-            let cd_command = format!("cd {}", path);
-            self.execute_direct_command(&cd_command).await;
+        if input == "models" {
+            self.list_models().await;
+            return;
+        }
+        if input == "model" || input.starts_with("model ") {
+            self.handle_model_command(&input["model".len()..]).await;
+            return;
+        }
+
+        if input == "context" || input.starts_with("context ") {
+            self.handle_context_command(&input["context".len()..]).await;
+            return;
         }
+
         // If it's a direct command, execute it
-        else if self.command_executor.is_valid_command(input) {
+        if self.command_executor.is_valid_command(input).await {
             self.execute_direct_command(input).await;
         } else {
             self.process_ai_command(input).await;
         }
     }
 
-    async fn execute_direct_command(&mut self, command: &str) {
-        match self.command_executor.execute(command).await {
+    /// Runs `command` and shows its output, without asking the AI about failures.
+    async fn run_command(&mut self, command: &str) -> TurnOutcome {
+        let result = self.command_executor.execute(command).await;
+        self.record_command(command);
+        let shown = self.command_executor.output_shown();
+        match result {
             Ok(output) => {
-                if !output.is_empty() {
-                    println!("{}", output);
+                if !output.is_empty() && !shown {
+                    self.interface.display_output(&output);
                 }
-                // Ensure output is flushed
-                std::io::stdout().flush().unwrap_or_default();
+                TurnOutcome::Executed { command: command.to_string(), success: true, output: redact(&output) }
+            }
+            Err(DiracError::Interrupted) => {
+                self.interface.display_output(&"⏹  Command interrupted.".yellow().to_string());
+                let output = format!("{}{}", self.command_executor.last_stdout(), self.command_executor.last_stderr());
+                TurnOutcome::Interrupted { command: command.to_string(), output: redact(&output) }
             }
             Err(e) => {
-                eprintln!("{}", e.to_string().red());
-                std::io::stderr().flush().unwrap_or_default();
+                self.interface.display_error(&e.to_string().red().to_string());
+                // Output already on screen is not part of the error
+                let output = if shown {
+                    let stderr = self.command_executor.last_stderr();
+                    let output = if stderr.trim().is_empty() { self.command_executor.last_stdout() } else { stderr };
+                    format!("{}: {}", e, output.trim())
+                } else {
+                    e.to_string()
+                };
+                TurnOutcome::Executed { command: command.to_string(), success: false, output: redact(&output) }
+            }
+        }
+    }
+
+    async fn execute_direct_command(&mut self, command: &str) {
+        let outcome = self.run_command(command).await;
+        self.offer_fixes(outcome).await;
+    }
+
+    /// What the AI is told about a failed command: the command, its exit
+    /// code, its error output and where it ran.
+    fn failure_report(&self, command: &str, output: &str) -> String {
+        let exit_code = match self.command_executor.last_exit_code() {
+            Some(code) => code.to_string(),
+            None => "none (the command timed out or could not be started)".to_string(),
+        };
+        let stderr = redact(&self.command_executor.last_stderr());
+        let stderr = if stderr.trim().is_empty() { output } else { stderr.trim() };
+        format!(
+            "failed command:\ncommand: {}\nexit code: {}\nworking directory: {}\nerror output:\n{}",
+            command,
+            exit_code,
+            self.command_executor.get_current_dir(),
+            tail(stderr, MAX_FAILURE_CHARS)
+        )
+    }
+
+    /// After a failed command, asks the AI for a corrected one and offers it
+    /// like any other suggestion, for as long as the fixes keep failing and
+    /// the user keeps accepting them. Each fix is remembered in the
+    /// conversation together with whether it worked.
+    async fn offer_fixes(&mut self, mut outcome: TurnOutcome) {
+        for attempt in 0..MAX_FIX_ATTEMPTS {
+            let TurnOutcome::Executed { command, success: false, output } = &outcome else {
+                return;
+            };
+            if attempt > 0 {
+                self.interface.display_output(&"❌ The fix did not work either.".red().bold().to_string());
             }
+
+            let history = self.conversation.messages();
+            let context = format!("{}\n\n{}", self.failure_report(command, output), self.prompt_context().await);
+            let response = match self.request(PromptKind::Diagnose, command, context.trim_end(), &history).await {
+                Ok(response) => response,
+                Err(e) => {
+                    self.handle_request_error(e).await;
+                    return;
+                }
+            };
+            let Some(fix) = self.render_suggestion(PromptKind::Diagnose, response, Some("Suggested Fix")).await else {
+                return;
+            };
+            if fix.command.is_empty() {
+                return;
+            }
+            self.display_suggestion_details(&fix);
+            let exit_code = self.command_executor.last_exit_code().map(|code| code.to_string()).unwrap_or_else(|| "none".to_string());
+            self.conversation.push_turn(&format!("`{}` failed with exit code {}; fix it", command, exit_code), &fix);
+
+            outcome = self.confirm_and_run(&fix.candidates()).await;
+            self.conversation.set_outcome(outcome.clone());
+            if let TurnOutcome::Executed { success: true, .. } = outcome {
+                self.interface.display_output(&"✅ The fix worked.".green().bold().to_string());
+                return;
+            }
+        }
+        if let TurnOutcome::Executed { success: false, .. } = outcome {
+            self.interface.display_error(&format!("❌ Still failing after {} fixes; giving up.", MAX_FIX_ATTEMPTS).red().bold().to_string());
         }
     }
 
     async fn process_ai_command(&mut self, input: &str) {
-        println!("{}", "🤖 Processing with AI...".yellow().bold());
-        println!("{} {}", "Request:".blue(), input);
-        println!("{}", "Analyzing request and generating command...".yellow());
+        self.interface.display_output(&"🤖 Processing with AI...".yellow().bold().to_string());
+        self.interface.display_output(&format!("{} {}", "Request:".blue(), input));
+        self.interface.display_output(&"Analyzing request and generating command...".yellow().to_string());
         
-        match self.ai_processor.process(input, String::new().as_str()).await {
-            Ok(suggested_command) => self.handle_ai_suggestion(suggested_command.as_str()).await,
-            Err(e) => self.handle_ai_error(e),
+        let history = self.conversation.messages();
+        let mut probe_results = Vec::new();
+        for round in 0..=self.max_probe_rounds {
+            let last_round = round == self.max_probe_rounds;
+            let mut context = self.prompt_context().await;
+            if !probe_results.is_empty() {
+                context.push_str(&format!("\n\nprobe results:\n{}", probe_results.join("\n\n")));
+            }
+            if last_round {
+                context.push_str("\n\nprobes:\nNo more probes can be run; answer with the final command now.");
+            }
+
+            let response = match self.request(PromptKind::Generate, input, context.trim_start(), &history).await {
+                Ok(response) => response,
+                Err(e) => {
+                    self.handle_request_error(e).await;
+                    return;
+                }
+            };
+            let banner = (round == 0).then_some("Command Suggestion");
+            let Some(suggestion) = self.render_suggestion(PromptKind::Generate, response, banner).await else {
+                return;
+            };
+            if suggestion.probes.is_empty() || last_round {
+                if suggestion.command.is_empty() {
+                    self.interface.display_error(&"❌ AI kept inspecting the system without settling on a command.".red().bold().to_string());
+                    self.interface.display_error(&"Try rephrasing your request or use more specific terms.".yellow().to_string());
+                    return;
+                }
+                self.offer_suggestion(input, suggestion).await;
+                return;
+            }
+
+            let cwd = std::path::PathBuf::from(self.command_executor.get_current_dir());
+            for probe in &suggestion.probes {
+                self.interface.display_output(&format!("{} {}", "🔎 Probe:".blue(), probe.to_string().cyan()));
+                let result = run_probe(probe, &cwd).await;
+                display_probe_result(&result, self.interface.as_ref());
+                probe_results.push(format!("$ {}\n{}", probe, result));
+            }
         }
     }
 
-    async fn handle_ai_suggestion(&mut self, suggested_command: &str) {
-        // Parse command and explanation from the AI response
-        let mut command = String::new();
-        let mut explanation = String::new();
-    
-        for line in suggested_command.lines() {
-            if line.starts_with("COMMAND:") {
-                command = line.trim_start_matches("COMMAND:").trim().to_string();
-            } else if line.starts_with("EXPLANATION:") {
-                explanation = line.trim_start_matches("EXPLANATION:").trim().to_string();
+    /// Sends an AI request that CTRL-C can cancel and waits for its first
+    /// token. Only when that is slow does it ask whether the model has to be
+    /// loaded, so cached answers and warm models cost no extra round-trip.
+    async fn request(&self, kind: PromptKind, input: &str, context: &str, history: &[ChatMessage]) -> DiracResult<AIResponse> {
+        let started = async {
+            let mut response = self.ai_processor.process_stream(kind, input, context, history).await?;
+            if let Some(first) = response.stream.next().await {
+                response.stream = Box::pin(futures_util::stream::once(async { first }).chain(response.stream));
+            }
+            Ok(response)
+        };
+        let loading_note = async {
+            tokio::time::sleep(SLOW_START).await;
+            if self.ai_processor.is_model_loaded().await == Some(false) {
+                let model = self.ai_processor.model().unwrap_or_else(|| self.ai_processor.name());
+                self.interface.display_output(&format!("⏳ Loading model {} into memory; the first answer may take a while...", model).yellow().to_string());
             }
+        };
+        self.cancellation
+            .race(async {
+                tokio::pin!(started, loading_note);
+                let mut noted = false;
+                loop {
+                    tokio::select! {
+                        response = &mut started => return response,
+                        _ = &mut loading_note, if !noted => noted = true,
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Prints a streamed answer as it arrives and parses it once complete.
+    async fn render_suggestion(&self, kind: PromptKind, response: AIResponse, banner: Option<&str>) -> Option<Suggestion> {
+        let AIResponse { provider, cached, mut stream } = response;
+        if let Some(title) = banner {
+            let source = if cached { format!("{}, cached", provider) } else { provider };
+            self.interface.display_output(&format!("\n=== {} ({}) =====", title, source).green().bold().to_string());
         }
-    
-        if command.is_empty() {
-            eprintln!("{}", "❌ AI could not generate a suitable command for your request.".red().bold());
-            eprintln!("{}", "Try rephrasing your request or use more specific terms.".yellow());
+
+        // Render the answer while the model is still generating it
+        let mut renderer = SuggestionRenderer {
+            prose: kind == PromptKind::Ask,
+            ..SuggestionRenderer::default()
+        };
+        loop {
+            let chunk = match self.cancellation.race(async { stream.next().await.transpose() }).await {
+                Ok(Some(chunk)) => Ok(chunk),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            match chunk {
+                Ok(chunk) => renderer.push(&chunk, self.interface.as_ref()),
+                Err(e) => {
+                    self.interface.display_output("");
+                    self.handle_ai_error(e);
+                    return None;
+                }
+            }
+        }
+        match renderer.finish(self.interface.as_ref()) {
+            Ok(suggestion) => Some(suggestion),
+            Err(e) => {
+                self.interface.display_error(&"❌ AI could not generate a suitable command for your request.".red().bold().to_string());
+                self.interface.display_error(&e.to_string().red().to_string());
+                self.interface.display_error(&"Try rephrasing your request or use more specific terms.".yellow().to_string());
+                None
+            }
+        }
+    }
+
+    async fn offer_suggestion(&mut self, request: &str, suggestion: Suggestion) {
+        self.display_suggestion_details(&suggestion);
+        self.conversation.push_turn(request, &suggestion);
+
+        let outcome = self.confirm_and_run(&suggestion.candidates()).await;
+        self.conversation.set_outcome(outcome.clone());
+        self.offer_fixes(outcome).await;
+    }
+
+    /// Handles `ask <question>`: answers from the output of the last
+    /// command, in prose or with a follow-up command to run.
+    async fn process_ask_command(&mut self, question: &str) {
+        let Some(last_output) = self.last_output.clone() else {
+            self.interface.display_error(&"No command has been run yet; run one, then ask about its output.".red().to_string());
+            return;
+        };
+        self.interface.display_output(&"🤖 Asking about the last output...".yellow().bold().to_string());
+        self.interface.display_output(&format!("{} {}", "Command:".blue(), last_output.command));
+        self.interface.display_output(&format!("{} {}", "Question:".blue(), question));
+
+        // The output gets a section of its own instead of the `output`
+        // provider's, which may be disabled or budgeted for other requests
+        let history = self.conversation.messages();
+        let context = format!("last output:\n{}\n\n{}", describe_output(&last_output), self.session_context(None).await);
+        let response = match self.request(PromptKind::Ask, question, context.trim_end(), &history).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.handle_request_error(e).await;
+                return;
+            }
+        };
+        let Some(answer) = self.render_suggestion(PromptKind::Ask, response, Some("Answer")).await else {
+            return;
+        };
+        if answer.command.is_empty() {
+            self.conversation.push_turn(question, &answer);
             return;
         }
-    
-        println!("{}", "\n=== Command Suggestion =====".green().bold());
-        println!("{} {}", "📎 Command:".blue(), command.yellow());
-        if !explanation.is_empty() {
-            println!("{} {}", "💡 Details:".blue(), explanation);
+        self.offer_suggestion(question, answer).await;
+    }
+
+    /// Handles `plan <request>`: asks for an ordered list of commands and
+    /// walks through them, offering a revised plan when a step fails.
+    async fn process_plan_command(&mut self, request: &str) {
+        self.interface.display_output(&"🗺️  Planning with AI...".yellow().bold().to_string());
+        self.interface.display_output(&format!("{} {}", "Request:".blue(), request));
+
+        let mut progress: Option<String> = None;
+        loop {
+            let history = self.conversation.messages();
+            let mut context = self.prompt_context().await;
+            if let Some(progress) = &progress {
+                context.push_str(&format!("\n\nplan progress:\n{}", progress));
+            }
+            let response = match self.request(PromptKind::Plan, request, context.trim_start(), &history).await {
+                Ok(response) => response,
+                Err(e) => {
+                    self.handle_request_error(e).await;
+                    return;
+                }
+            };
+            let Some(plan) = self.render_suggestion(PromptKind::Plan, response, Some("Plan")).await else {
+                return;
+            };
+            if plan.steps.is_empty() {
+                self.interface.display_error(&"❌ AI did not break the request down into steps.".red().bold().to_string());
+                self.interface.display_error(&"Try rephrasing your request or use more specific terms.".yellow().to_string());
+                return;
+            }
+            if plan.requires_sudo {
+                self.interface.display_output(&"🔒 Some steps require root privileges.".yellow().to_string());
+            }
+            self.conversation.push_turn(request, &plan);
+
+            let (outcome, failure) = self.run_plan(&plan.steps).await;
+            self.conversation.set_outcome(outcome);
+            let Some(report) = failure else {
+                return;
+            };
+            self.interface.display_output(&"\nAsk the AI for a revised plan? [y/N]:".yellow().to_string());
+            match self.interface.read_line("") {
+                Ok(answer) if answer.trim().eq_ignore_ascii_case("y") => progress = Some(report),
+                _ => return,
+            }
         }
-    
-        // Only show execution prompt if we have a valid command
-        println!("{}", "\nWould you like to execute this command? [y/N/e(explain)]:".yellow());
-    
-        if let Ok(confirmation) = self.editor.readline("") {
-            match confirmation.trim().to_lowercase().as_str() {
-                "y" => self.execute_direct_command(&command).await,
-                "e" => {
-                    if !explanation.is_empty() {
-                        println!("{}", "\n=== Command Explanation ====".blue().bold());
-                        println!("{}", explanation);
-                        println!("{}", "\nWould you like to execute this command now? [y/N]:".yellow());
-                        if let Ok(second_confirmation) = self.editor.readline("") {
-                            if second_confirmation.trim().to_lowercase() == "y" {
-                                self.execute_direct_command(&command).await;
-                            }
+    }
+
+    /// Handles `explain <command>`: breaks the command down token by token
+    /// from the local documentation, has the AI explain it as a whole and
+    /// sums up the risks. The command itself is never run.
+    async fn process_explain_command(&mut self, command: &str) {
+        let pipeline = match Pipeline::parse(command) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                self.interface.display_error(&format!("❌ Could not parse the command: {}", e).red().to_string());
+                return;
+            }
+        };
+
+        let mut references = HashMap::new();
+        let mut rows = Vec::new();
+        for (index, simple) in pipeline.commands.iter().enumerate() {
+            rows.extend(Self::annotate(simple, &mut references).await);
+            if let Some(connector) = pipeline.connectors.get(index) {
+                rows.push((0, connector.symbol().to_string(), connector.description().to_string()));
+            }
+        }
+
+        self.interface.display_output(&"\n=== Breakdown =====".green().bold().to_string());
+        let width = rows
+            .iter()
+            .map(|(depth, token, _)| depth * 2 + token.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(MAX_TOKEN_WIDTH);
+        for (depth, token, annotation) in &rows {
+            let indent = "  ".repeat(*depth);
+            let padding = (width + 2).saturating_sub(indent.len() + token.chars().count()).max(2);
+            let token = if *depth == 0 { token.cyan().bold() } else { token.cyan() };
+            self.interface.display_output(&format!("  {}{}{}{}", indent, token, " ".repeat(padding), annotation.dimmed()));
+        }
+
+        // The AI sees the local breakdown so it can build on what the man
+        // pages say instead of guessing flags
+        let breakdown: Vec<String> = rows
+            .iter()
+            .map(|(depth, token, annotation)| format!("{}{}: {}", "  ".repeat(*depth), token, annotation))
+            .collect();
+        let context = format!("{}\n\nlocal breakdown:\n{}", self.prompt_context().await, breakdown.join("\n"));
+        let history = self.conversation.messages();
+        let suggestion = match self.request(PromptKind::Explain, command, context.trim_start(), &history).await {
+            Ok(response) => self.render_suggestion(PromptKind::Explain, response, Some("Explanation")).await,
+            Err(e) => {
+                self.handle_request_error(e).await;
+                None
+            }
+        };
+
+        let findings = pipeline.risk_findings();
+        let local_risk = findings.iter().map(|finding| finding.risk).max().unwrap_or(Risk::Low);
+        self.interface.display_output(&"\n=== Risk Summary =====".green().bold().to_string());
+        match &suggestion {
+            Some(suggestion) => self.interface.display_output(&format!("{} {}", "⚠️  Risk:".blue(), colored_risk(suggestion.risk.max(local_risk)))),
+            None => self.interface.display_output(&format!("{} {} {}", "⚠️  Risk:".blue(), colored_risk(local_risk), "(local analysis only)".dimmed())),
+        }
+        for finding in &findings {
+            self.interface.display_output(&format!("   • {} [{}]", finding.reason, colored_risk(finding.risk)));
+        }
+        if let Some(suggestion) = &suggestion {
+            if suggestion.requires_sudo {
+                self.interface.display_output(&"🔒 This command requires root privileges.".yellow().to_string());
+            }
+            if !suggestion.alternatives.is_empty() {
+                self.interface.display_output(&"🔀 Alternatives:".blue().to_string());
+                for alternative in &suggestion.alternatives {
+                    self.interface.display_output(&format!("   • {} [{}]", alternative.command.yellow(), colored_risk(alternative.risk)));
+                    if !alternative.explanation.is_empty() {
+                        self.interface.display_output(&format!("     {}", alternative.explanation));
+                    }
+                }
+            }
+            self.conversation.push_turn(&format!("explain {}", command), suggestion);
+        }
+    }
+
+    /// Rows of `(depth, token, annotation)` for one command of a pipeline:
+    /// the program at depth 0, its arguments and redirections below it.
+    async fn annotate(command: &SimpleCommand, references: &mut HashMap<String, ProgramReference>) -> Vec<(usize, String, String)> {
+        let mut rows = Vec::new();
+        for assignment in &command.assignments {
+            let name = assignment.split('=').next().unwrap_or_default();
+            rows.push((0, assignment.clone(), format!("sets {} for this command only", name)));
+        }
+
+        // Arguments from the effective program on are documented by it, the
+        // ones before by the wrapper such as `sudo`
+        let mut owners = Vec::new();
+        if let Some(program) = &command.program {
+            owners.push((0, program.as_str()));
+            if let Some((effective, rest)) = command.effective().filter(|(effective, _)| effective != program) {
+                owners.push((command.args.len() - rest.len() - 1, effective));
+            }
+        }
+        for (_, program) in &owners {
+            if !references.contains_key(*program) {
+                references.insert(program.to_string(), ProgramReference::lookup(program).await);
+            }
+        }
+        let describe_program = |program: &str| {
+            if SHELL_BUILTINS.contains(&program) {
+                return "shell builtin".to_string();
+            }
+            match references.get(program).and_then(|reference| reference.summary.clone()) {
+                Some(summary) => summary,
+                None if which::which(program).is_ok() => "program".to_string(),
+                None => "not installed or not on the PATH".to_string(),
+            }
+        };
+
+        if let Some(program) = &command.program {
+            rows.push((0, program.clone(), describe_program(program)));
+        }
+        let mut owner = command.program.as_deref().unwrap_or_default();
+        let mut depth = 1;
+        for (index, arg) in command.args.iter().enumerate() {
+            if let Some((_, effective)) = owners.iter().skip(1).find(|(position, _)| *position == index) {
+                owner = effective;
+                rows.push((1, arg.clone(), describe_program(effective)));
+                depth = 2;
+                continue;
+            }
+            let reference = references.get(owner);
+            let lookup = |flag: &str| reference.and_then(|reference| reference.describe_flag(flag));
+            if arg.starts_with('-') && arg.len() > 1 && arg != "--" {
+                match lookup(arg) {
+                    Some(description) => rows.push((depth, arg.clone(), description)),
+                    // Combined short flags such as `-la`
+                    None if !arg.starts_with("--") && arg.len() > 2 && arg[1..].chars().all(|c| c.is_ascii_alphabetic()) => {
+                        rows.push((depth, arg.clone(), "combined short options".to_string()));
+                        for flag in arg[1..].chars() {
+                            let flag = format!("-{}", flag);
+                            let description = lookup(&flag).unwrap_or_else(|| "option".to_string());
+                            rows.push((depth + 1, flag, description));
                         }
-                    } else {
-                        println!("{}", "No detailed explanation available for this command.".yellow());
                     }
+                    None => rows.push((depth, arg.clone(), "option".to_string())),
+                }
+            } else {
+                rows.push((depth, arg.clone(), "argument".to_string()));
+            }
+        }
+        for redirection in &command.redirections {
+            rows.push((1, redirection.to_string(), redirection.description()));
+        }
+        rows
+    }
+
+    /// Walks through the steps of a plan, letting the user run, edit or skip
+    /// each one, and stops at the first failure or interruption. Returns the
+    /// outcome of the last step run and, after a failure, a progress report
+    /// for revising the plan.
+    async fn run_plan(&mut self, steps: &[Candidate]) -> (TurnOutcome, Option<String>) {
+        let mut progress = Vec::new();
+        let mut outcome = TurnOutcome::Declined;
+        for (index, step) in steps.iter().enumerate() {
+            let number = index + 1;
+            self.interface.display_output(&format!(
+                "\n{} {} [{}]",
+                format!("Step {}/{}:", number, steps.len()).blue().bold(),
+                step.command.yellow(),
+                colored_risk(step.risk)
+            ));
+            if !step.explanation.is_empty() {
+                self.interface.display_output(&format!("   {}", step.explanation));
+            }
+            self.interface.display_output(&"Run this step? [y/e(edit)/s(skip)/N(stop)]:".yellow().to_string());
+
+            let answer = self.interface.read_line("").unwrap_or_default();
+            let command = match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => step.command.clone(),
+                "e" | "edit" => match self.interface.read_line_with_initial("edit> ", &step.command) {
+                    Ok(edited) if !edited.trim().is_empty() => edited.trim().to_string(),
+                    _ => {
+                        self.interface.display_output(&"Plan stopped.".yellow().to_string());
+                        return (outcome, None);
+                    }
+                },
+                "s" | "skip" => {
+                    progress.push(format!("{}. `{}` - skipped", number, step.command));
+                    continue;
+                }
+                _ => {
+                    self.interface.display_output(&"Plan stopped.".yellow().to_string());
+                    return (outcome, None);
+                }
+            };
+
+            outcome = self.run_command(&command).await;
+            if let TurnOutcome::Interrupted { .. } = &outcome {
+                self.interface.display_output(&"Plan stopped.".yellow().to_string());
+                return (outcome, None);
+            }
+            if let TurnOutcome::Executed { success: false, output, .. } = &outcome {
+                progress.push(format!("{}. `{}` - failed:\n{}", number, command, tail(output, MAX_FAILURE_CHARS)));
+                for (later, step) in steps.iter().enumerate().skip(number) {
+                    progress.push(format!("{}. `{}` - not run", later + 1, step.command));
+                }
+                self.interface.display_error(&format!("❌ Step {} failed; the remaining steps were not run.", number).red().bold().to_string());
+                return (outcome, Some(progress.join("\n")));
+            }
+            progress.push(format!("{}. `{}` - succeeded", number, command));
+        }
+        self.interface.display_output(&"\n✅ Plan complete.".green().bold().to_string());
+        (outcome, None)
+    }
+
+    /// Lets the user pick one of the ranked candidates, optionally editing it,
+    /// and runs it. Failures are left to the caller, which records the
+    /// outcome before offering fixes.
+    async fn confirm_and_run(&mut self, candidates: &[Candidate]) -> TurnOutcome {
+        if candidates.len() > 1 {
+            self.interface.display_output(&format!("\nRun which command? [1-{}/N, e<n> to edit first]:", candidates.len()).yellow().to_string());
+        } else {
+            self.interface.display_output(&"\nWould you like to execute this command? [y/N/e(edit)]:".yellow().to_string());
+        }
+
+        let choice = match self.interface.read_line("") {
+            Ok(answer) => parse_candidate_choice(&answer, candidates.len()),
+            Err(_) => CandidateChoice::Cancel,
+        };
+        let command = match choice {
+            CandidateChoice::Run(index) => candidates[index].command.clone(),
+            CandidateChoice::Edit(index) => {
+                match self.interface.read_line_with_initial("edit> ", &candidates[index].command) {
+                    Ok(edited) if !edited.trim().is_empty() => edited.trim().to_string(),
+                    _ => {
+                        self.interface.display_output(&"Command execution cancelled.".yellow().to_string());
+                        return TurnOutcome::Declined;
+                    }
+                }
+            }
+            CandidateChoice::Cancel => {
+                self.interface.display_output(&"Command execution cancelled.".yellow().to_string());
+                return TurnOutcome::Declined;
+            }
+        };
+        self.run_command(&command).await
+    }
+
+    fn display_suggestion_details(&self, suggestion: &Suggestion) {
+        self.interface.display_output(&format!("{} {}", "⚠️  Risk:".blue(), colored_risk(suggestion.risk)));
+        if suggestion.requires_sudo {
+            self.interface.display_output(&"🔒 This command requires root privileges.".yellow().to_string());
+        }
+        if !suggestion.alternatives.is_empty() {
+            self.interface.display_output(&"🔀 Alternatives:".blue().to_string());
+            for (rank, alternative) in suggestion.alternatives.iter().enumerate() {
+                self.interface.display_output(&format!("   {}) {} [{}]", rank + 2, alternative.command.yellow(), colored_risk(alternative.risk)));
+                if !alternative.explanation.is_empty() {
+                    self.interface.display_output(&format!("      {}", alternative.explanation));
                 }
-                _ => println!("{}", "Command execution cancelled.".yellow())
             }
         }
     }
 
     fn handle_ai_error(&self, error: DiracError) {
-        eprintln!("{}", "Error processing with AI:".red());
-        eprintln!("{}", error.to_string().red());
-        eprintln!("{}", "Please ensure the Ollama service is running correctly.".yellow());
+        if let DiracError::Interrupted = error {
+            self.interface.display_output(&"⏹  AI request cancelled.".yellow().to_string());
+            return;
+        }
+        if let DiracError::ModelLoading(_) = &error {
+            self.interface.display_error(&format!("⏳ {}; try again in a moment.", error).yellow().to_string());
+            return;
+        }
+        if let DiracError::ModelNotFound(model) = &error {
+            self.interface.display_error(&error.to_string().red().to_string());
+            let hint = match self.models {
+                Some(_) => format!("Download it with 'model pull {}' or 'ollama pull {}'.", model, model),
+                None => "Pass a model the server serves with --model.".to_string(),
+            };
+            self.interface.display_error(&hint.yellow().to_string());
+            return;
+        }
+        self.interface.display_error(&"Error processing with AI:".red().to_string());
+        self.interface.display_error(&error.to_string().red().to_string());
+        self.interface.display_error(&"Please ensure the AI service is running correctly.".yellow().to_string());
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::mock::{ScriptedAIProcessor, ScriptedTerminal};
+    use std::path::PathBuf;
+
+    /// A path of its own under the system's temporary directory, not yet
+    /// created.
+    fn scratch_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dirac-terminal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// A terminal answering from `ai` and reading `inputs`, with a handle on
+    /// what it displays.
+    fn scripted(ai: &ScriptedAIProcessor, inputs: &[&str]) -> (DiracTerminal, ScriptedTerminal) {
+        colored::control::set_override(false);
+        let interface = ScriptedTerminal::new(inputs.iter().copied());
+        let terminal = DiracTerminal::with_interface(Box::new(ai.clone()), Box::new(interface.clone()));
+        (terminal, interface)
+    }
+
+    #[tokio::test]
+    async fn confirmed_suggestion_is_executed() {
+        let marker = scratch_file("confirmed");
+        let command = format!("touch {}", marker.display());
+        let ai = ScriptedAIProcessor::new().suggest("marker", &command, "Creates the marker file");
+        let (mut terminal, interface) = scripted(&ai, &["y"]);
+
+        terminal.process_command("I want the marker file").await;
+
+        let output = interface.output();
+        assert!(output.iter().any(|line| line == &format!("📎 Command: {}", command)), "{:?}", output);
+        assert!(output.iter().any(|line| line == "💡 Details: Creates the marker file"), "{:?}", output);
+        assert!(output.iter().any(|line| line.contains("Would you like to execute this command?")), "{:?}", output);
+        assert!(marker.exists());
+        let _ = std::fs::remove_file(&marker);
+        assert_eq!(ai.requests().len(), 1);
+        assert_eq!(ai.requests()[0].kind, PromptKind::Generate);
+    }
+
+    #[tokio::test]
+    async fn declined_suggestion_is_not_executed() {
+        let marker = scratch_file("declined");
+        let ai = ScriptedAIProcessor::new().suggest("marker", &format!("touch {}", marker.display()), "");
+        let (mut terminal, interface) = scripted(&ai, &["n"]);
+
+        terminal.process_command("I want the marker file").await;
+
+        assert!(interface.output().iter().any(|line| line == "Command execution cancelled."));
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn ai_errors_are_displayed_as_errors() {
+        let ai = ScriptedAIProcessor::new();
+        let (mut terminal, interface) = scripted(&ai, &[]);
+
+        terminal.process_command("something nobody scripted").await;
+
+        let errors = interface.errors();
+        assert!(errors.iter().any(|line| line.contains("No scripted response")), "{:?}", errors);
+    }
+
+    #[tokio::test]
+    async fn fast_answers_skip_the_model_status_check() {
+        let ai = ScriptedAIProcessor::new().suggest("", "ls", "");
+        let (mut terminal, interface) = scripted(&ai, &["n"]);
+
+        terminal.process_command("show me the files").await;
+
+        assert_eq!(ai.status_checks(), 0);
+        assert!(!interface.output().iter().any(|line| line.contains("Loading model")));
+    }
+
+    #[tokio::test]
+    async fn slow_first_token_notes_a_loading_model() {
+        let ai = ScriptedAIProcessor::new().suggest("", "ls", "").cold(SLOW_START + Duration::from_millis(300));
+        let (mut terminal, interface) = scripted(&ai, &["n"]);
+
+        terminal.process_command("show me the files").await;
+
+        assert_eq!(ai.status_checks(), 1);
+        let output = interface.output();
+        let note = output.iter().position(|line| line.contains("Loading model")).expect("no loading note");
+        let command = output.iter().position(|line| line == "📎 Command: ls").expect("no suggestion");
+        assert!(note < command, "{:?}", output);
+    }
+
+}